@@ -40,6 +40,8 @@ function focusSearch(evt) {
     searchInput?.focus()
 }
 
-hotkey("n", () => goto("/new"))
-hotkey("h", () => goto("/"))
+const prefix = document.body.dataset.prefix ?? ""
+
+hotkey("n", () => goto(`${prefix}/new`))
+hotkey("h", () => goto(`${prefix}/`))
 hotkey("s", focusSearch)
\ No newline at end of file
@@ -0,0 +1,54 @@
+// Show entries matching the search box's contents as they're typed.
+
+const suggestForm = document.getElementById("search")
+const suggestInput = suggestForm?.querySelector("input[name='q']")
+const suggestList = document.getElementById("search-suggestions")
+const suggestPrefix = document.body.dataset.prefix ?? ""
+
+let suggestTimer = undefined
+
+function clearSuggestions() {
+    suggestList.replaceChildren()
+    suggestList.hidden = true
+}
+
+async function fetchSuggestions() {
+    const q = suggestInput.value.trim()
+    if (q === "") {
+        clearSuggestions()
+        return
+    }
+    const url = `${suggestPrefix}/api/suggest?q=${encodeURIComponent(q)}`
+    const response = await fetch(url)
+    if (!response.ok || suggestInput.value.trim() !== q) {
+        return
+    }
+    const suggestions = await response.json()
+    suggestList.replaceChildren(...suggestions.map((s) => {
+        const link = document.createElement("a")
+        link.href = s.url
+        link.textContent = `${s.date} · ${s.title}`
+        const item = document.createElement("li")
+        item.append(link)
+        return item
+    }))
+    suggestList.hidden = suggestions.length === 0
+}
+
+if (suggestInput && suggestList) {
+    suggestInput.setAttribute("autocomplete", "off")
+    suggestInput.addEventListener("input", () => {
+        clearTimeout(suggestTimer)
+        suggestTimer = setTimeout(fetchSuggestions, 150)
+    })
+    suggestInput.addEventListener("keydown", (evt) => {
+        if (evt.key === "Escape") {
+            clearSuggestions()
+        }
+    })
+    document.addEventListener("click", (evt) => {
+        if (!suggestForm.contains(evt.target)) {
+            clearSuggestions()
+        }
+    })
+}
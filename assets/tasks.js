@@ -0,0 +1,30 @@
+// Let task list checkboxes on an entry's page be ticked, saving each change to the entry.
+
+const taskEntry = document.querySelector("#entry-body[data-tasks-editable]")
+const taskPrefix = document.body.dataset.prefix ?? ""
+
+async function toggleTask(box) {
+    box.disabled = true
+    const form = new URLSearchParams({
+        task: box.dataset.task,
+        updated_at: taskEntry.dataset.updatedAt,
+    })
+    const url = `${taskPrefix}/entry/${taskEntry.dataset.entry}/toggle-task`
+    const response = await fetch(url, { method: "POST", body: form })
+    if (response.ok) {
+        const toggled = await response.json()
+        box.checked = toggled.checked
+        taskEntry.dataset.updatedAt = toggled.updated_at
+    } else {
+        box.checked = !box.checked
+        alert((await response.json()).error)
+    }
+    box.disabled = false
+}
+
+if (taskEntry) {
+    for (const box of taskEntry.querySelectorAll("input[data-task]")) {
+        box.disabled = false
+        box.addEventListener("change", () => toggleTask(box))
+    }
+}
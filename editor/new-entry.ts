@@ -20,8 +20,23 @@ function setup() {
     if (textarea.parentElement == null) {
         return;
     }
+    // The initial text is either the saved draft or an unedited copy of an entry; neither
+    // needs saving until it changes.
+    savedDraft = textarea.value;
+    // A recovered draft was saved without a session; this tab takes it over when it saves.
+    adoptDraft = document.getElementById("recovered-draft") !== null;
+    const session = document.querySelector<HTMLInputElement>('input[name="session"]');
+    if (session !== null) {
+        session.value = draftSession();
+    }
+    restoreDraft(view);
     setInterval(() => saveDraft(view), 6000);
 
+    const discard = document.getElementById("discard-draft");
+    if (discard !== null) {
+        discard.onclick = () => discardDraft(view);
+    }
+
     textarea.parentElement.onsubmit = () => {
         if (textarea != null) {
             const text = (view.state.doc as unknown) as string;
@@ -31,15 +46,94 @@ function setup() {
 }
 
 let savedDraft = "";
+let adoptDraft = false;
+
+interface DraftStatus {
+    saved_at: string | null,
+}
+
+interface SessionDraft extends DraftStatus {
+    body: string | null,
+    other_drafts: number,
+}
+
+// Each tab keeps its own draft, so autosaves from two tabs don't overwrite each other.
+// sessionStorage keeps the id across reloads but not between tabs.
+function draftSession(): string {
+    let session = sessionStorage.getItem("draft-session");
+    if (session === null) {
+        const bytes = crypto.getRandomValues(new Uint8Array(16));
+        session = Array.from(bytes, (b) => b.toString(16).padStart(2, "0")).join("");
+        sessionStorage.setItem("draft-session", session);
+    }
+    return session;
+}
+
+function draftUrl(path: string): string {
+    return `${path}?session=${encodeURIComponent(draftSession())}`;
+}
+
+function renderDraftStatus(status: DraftStatus) {
+    const element = document.getElementById("draft-status");
+    if (element === null || status.saved_at === null) {
+        return;
+    }
+    const savedAt = new Date(status.saved_at);
+    const time = savedAt.toLocaleTimeString([], { hour: "2-digit", minute: "2-digit" });
+    element.textContent = `Draft saved at ${time}`;
+}
+
+// Bring back this tab's draft after a reload, unless the page already has text in it.
+async function restoreDraft(view: EditorView): Promise<void> {
+    const result = await fetch(draftUrl("draft"));
+    if (!result.ok) {
+        return;
+    }
+    const draft: SessionDraft = await result.json();
+    if (draft.body !== null && view.state.doc.length == 0) {
+        view.dispatch({ changes: { from: 0, insert: draft.body } });
+        savedDraft = draft.body;
+    }
+    if (draft.body !== null) {
+        renderDraftStatus(draft);
+    }
+    const others = document.getElementById("other-drafts");
+    if (others !== null) {
+        others.hidden = draft.other_drafts == 0;
+    }
+}
+
+async function discardDraft(view: EditorView): Promise<void> {
+    if (!confirm("Discard this draft?")) {
+        return;
+    }
+    const url = draftUrl("draft/clear") + (adoptDraft ? "&adopt=true" : "");
+    const result = await fetch(url, { method: "post" });
+    if (!result.ok) {
+        return;
+    }
+    adoptDraft = false;
+    view.dispatch({ changes: { from: 0, to: view.state.doc.length, insert: "" } });
+    savedDraft = "";
+    document.getElementById("recovered-draft")?.remove();
+    const status = document.getElementById("draft-status");
+    if (status !== null) {
+        status.textContent = "Draft discarded";
+    }
+}
 
 async function saveDraft(view: EditorView): Promise<void> {
     const draft = (view.state.doc as unknown) as string;
     const formData = new URLSearchParams();
     formData.append("body", draft);
+    formData.append("session", draftSession());
+    if (adoptDraft) {
+        formData.append("adopt", "true");
+    }
     if (draft == savedDraft) {
         return
     } else {
-        const result = await fetch("/draft", {
+        const result = await fetch("draft", {
             method: "post",
             body: formData.toString(),
             headers: {
@@ -48,6 +142,8 @@ async function saveDraft(view: EditorView): Promise<void> {
         });
         if (result.ok) {
             savedDraft = draft;
+            adoptDraft = false;
+            renderDraftStatus(await result.json());
         }
     }
 }
@@ -7,10 +7,15 @@ should be "{year}-{month}-{day}-{hour}-{minute}.md"
 For example,
 
     2023-2-3-15-59.md
+
+Pass --plain to import plain text instead of markdown. Line breaks
+are preserved as markdown hard breaks so the entries look the way
+they did in the source instead of having their lines run together.
 """
 import datetime
 import pathlib
 import sqlite3
+import sys
 import zoneinfo
 
 LOCAL_TIMEZONE = "America/Vancouver"
@@ -26,10 +31,26 @@ def get_date(timestamp: int) -> str:
 def add_entry(cursor: sqlite3.Cursor, timestamp: int, body: str):
     date = get_date(timestamp)
     cursor.execute("""
-    INSERT INTO entries (timestamp, date, body)
-    VALUES (?, ?, ?)
-    """, [timestamp, date, body])
-    cursor.execute("INSERT INTO entrytext (body) VALUES (?)", [body])
+    INSERT INTO entries (timestamp, date, body, updated_at)
+    VALUES (?, ?, ?, ?)
+    """, [timestamp, date, body, timestamp])
+    cursor.execute(
+        "INSERT INTO entrytext (rowid, body) VALUES (?, ?)",
+        [cursor.lastrowid, body],
+    )
+
+
+def plain_to_markdown(text: str) -> str:
+    lines = text.splitlines()
+    converted = []
+    for line, following in zip(lines, lines[1:] + [""]):
+        if line.strip() and following.strip():
+            # Two trailing spaces make a hard line break in CommonMark.
+            converted.append(line.rstrip() + "  ")
+        else:
+            converted.append(line)
+    trailing = "\n" if text.endswith("\n") else ""
+    return "\n".join(converted) + trailing
 
 
 def ts_for(y, mo, d, h, mi):
@@ -37,6 +58,7 @@ def ts_for(y, mo, d, h, mi):
     return int(dt.timestamp())
 
 if __name__ == "__main__":
+    plain = "--plain" in sys.argv[1:]
     inputdirname = "docs"
     docs = pathlib.Path(inputdirname)
     assert docs.exists() and docs.is_dir()
@@ -45,4 +67,7 @@ if __name__ == "__main__":
         for f in docs.iterdir():
             if f.is_file():
                 ts = ts_for(*[int(n) for n in f.stem.split("-")])
-                add_entry(cursor, ts, f.read_text())
+                body = f.read_text()
+                if plain:
+                    body = plain_to_markdown(body)
+                add_entry(cursor, ts, body)
@@ -9,17 +9,36 @@ use axum::{
     extract::{Extension, Form, Path, Query},
     http::StatusCode,
     response::{Html, Redirect},
+    Json,
 };
-use chrono::{DateTime, NaiveDate, Utc};
-use log::{error, info};
+use chrono::{DateTime, FixedOffset, NaiveDate, Utc};
+use log::{debug, error, info, warn};
 use rusqlite::{Connection, OptionalExtension};
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
     pretty_env_logger::init();
-    info!("Initializing");
 
-    let (dbpath, host, port) = match get_parameters() {
+    // Maintenance commands run against the database and exit without starting the server.
+    let command: Option<Command> = match std::env::args().nth(1).as_deref() {
+        Some("import-dir") => Some(import_dir),
+        Some("reindex") => Some(reindex_command),
+        Some("check") => Some(check_command),
+        _ => None,
+    };
+    if let Some(command) = command {
+        match command(std::env::args().skip(2).collect()) {
+            Ok(report) => println!("{}", report),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    info!("Initializing");
+    let config = match get_parameters() {
         Ok(params) => params,
         Err(msg) => {
             eprintln!("{}", msg);
@@ -27,10 +46,93 @@ async fn main() {
         }
     };
 
-    info!("Connecting to database: {}", dbpath);
-    let cxn = connect_and_init_db(&dbpath).expect("Error initializing database.");
-    let addr = SocketAddr::new(host, port);
-    let app = newapp(cxn);
+    if config.print_config {
+        match serde_json::to_string_pretty(&config) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Couldn't serialize configuration: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let cipher = config.encryption_key_file.as_deref().map(|path| {
+        load_cipher(path).unwrap_or_else(|msg| {
+            eprintln!("{}", msg);
+            std::process::exit(1);
+        })
+    });
+
+    let prompts = config
+        .prompts_file
+        .as_deref()
+        .map_or_else(Vec::new, |path| {
+            load_prompts(path).unwrap_or_else(|msg| {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            })
+        });
+
+    let mut cxns = Vec::new();
+    for diary in config.diaries {
+        info!("Connecting to database: {}", diary.dbpath);
+        let cxn = connect_and_init_db(&diary.dbpath, cipher.clone(), config.fts_tokenizer)
+            .unwrap_or_else(|msg| {
+                eprintln!("Error initializing database {}: {}", diary.dbpath, msg);
+                std::process::exit(1);
+            });
+        if let Some(webhook) = &config.reminder_webhook {
+            tokio::spawn(remind_to_write(
+                diary.name.clone(),
+                diary.dbpath.clone(),
+                webhook.clone(),
+                config.reminder_hours,
+            ));
+            if config.streak_notifications {
+                tokio::spawn(watch_streak(
+                    diary.name.clone(),
+                    diary.dbpath.clone(),
+                    webhook.clone(),
+                ));
+            }
+        }
+        cxns.push((diary.name, cxn));
+    }
+    let addr = SocketAddr::new(config.host, config.port);
+    let options = SiteOptions {
+        title: config.site_title,
+        author: config.author,
+        markdown: MarkdownOptions {
+            trust_html: config.trust_html,
+            smart_punctuation: config.smart_punctuation,
+            math: config.math,
+        },
+        read_only: config.read_only,
+        duplicate_window_secs: config.duplicate_window_secs,
+        idempotency_window_secs: config.idempotency_window_secs,
+        entries_per_month: config.entries_per_month,
+        index_preview_length: config.index_preview_length,
+        prompts,
+        base_path: config.base_path,
+        // Filled in by `newapp` from the static directory.
+        asset_version: String::new(),
+        max_query_length: config.max_query_length,
+        max_search_results: config.max_search_results,
+        snippet: SnippetOptions {
+            start: config.snippet_start,
+            end: config.snippet_end,
+            ellipsis: config.snippet_ellipsis,
+        },
+        locale: config.locale,
+        week_start: config.week_start,
+        db_lock_timeout: std::time::Duration::from_millis(config.db_lock_timeout_ms),
+    };
+    if options.markdown.trust_html {
+        warn!("--trust-html is set: entry HTML will NOT be sanitized. Only use this for a private, single-user diary.");
+    }
+    let slow_request = std::time::Duration::from_millis(config.slow_request_ms);
+    let app = newapp(cxns, options, slow_request);
     info!("Listening on {}", addr);
     axum::Server::bind(&addr)
         .serve(app.into_make_service())
@@ -39,35 +141,646 @@ async fn main() {
 }
 
 const USAGE: &str = r#"
-web-diary-rs <dbpath> <host> <port>
+web-diary-rs <dbpath> <host> <port> [options]
+web-diary-rs import-dir <dbpath> <dir> [--encryption-key-file <path>]
+                        [--fts-tokenizer <name>]
+web-diary-rs reindex <dbpath> [--fts-tokenizer <name>]
+web-diary-rs check <dbpath> [--encryption-key-file <path>]
+
+  import-dir: Import YYYY-MM-DD.txt files from dir as entries, skipping
+              days that already have one.
+  reindex:    Rebuild the search index from the entries, e.g. after it
+              has drifted, without starting the server.
+  check:      Check the database for corruption and entries the server
+              couldn't show. Exits nonzero if there are problems.
 
-  dbpath:   Path to the app's SQLite database
+  dbpath:   Path to the app's SQLite database, or a comma separated list
+            of name=path pairs to host several diaries under /d/<name>/
+            (e.g. personal=personal.sqlite3,work=work.sqlite3)
   host:     Host to bind (e.g. 0.0.0.0)
   port:     Port to bind (e.g. 8088)
+
+options:
+  --encryption-key-file <path>
+            Encrypt entry and draft bodies with the 32-byte key in this
+            file (64 hex digits). Existing plaintext entries stay
+            readable, and search uses an in-memory index.
+  --fts-tokenizer <unicode61|porter|trigram>
+            How search splits entries into terms (default unicode61).
+            porter matches other forms of English words; trigram
+            matches any substring of three or more characters. The
+            search index is rebuilt when this changes.
+  --trust-html
+            Don't sanitize HTML in entries. Only for private, single-user
+            diaries: anyone who can write an entry can run scripts in
+            readers' browsers.
+  --no-smart-punctuation
+            Keep straight quotes, dashes and ellipses in entries as
+            typed instead of turning them into typographic ones.
+  --math
+            Render TeX between $...$ (inline) and $$...$$ (display) as
+            math. Dollar signs in code, escaped as \$, or followed by a
+            digit (like prices) are left alone.
+  --site-title <title>
+            Title shown on every page (default "Diary").
+  --author <name>
+            Name of the diary's author, added to pages' metadata.
+  --read-only
+            Refuse to create or change entries and drafts, e.g. while
+            the database is being backed up. Pages can still be read.
+  --duplicate-window-secs <secs>
+            Treat a new entry identical to one saved this many seconds
+            ago as a double submission and don't save it again
+            (default 10; 0 turns this off).
+  --idempotency-window-secs <secs>
+            How long POST /api/entry remembers Idempotency-Key headers
+            (default 86400).
+  --entries-per-month <n>
+            How many of each month's entries the year page lists before
+            linking to the month (default 10).
+  --index-preview-length <chars>
+            Show this much of each recent entry on the index, cutting
+            longer ones short with a link to the rest (default 300; 0
+            shows none).
+  --max-query-length <chars>
+            Reject search queries longer than this (default 256).
+  --max-search-results <n>
+            Stop counting and paging through search results after this
+            many, so searches for common words stay fast (default 10000).
+  --snippet-start <html>, --snippet-end <html>
+            HTML put around matching terms in search results (default
+            <mark> and </mark>).
+  --snippet-ellipsis <text>
+            Text marking where search result snippets are cut off
+            (default "...").
+  --prompts-file <path>
+            Show one of the journaling prompts in this file, one per
+            line, on the new entry page. The prompt changes daily.
+  --locale <en|de|es|fr|it|nl|pt>
+            Language for month names (default en).
+  --week-start <monday|sunday>
+            First day of the week for "this week" on the index page
+            (default monday).
+  --base-path <path>
+            Serve the app under this path (e.g. /diary) instead of the
+            root, for running behind a reverse proxy.
+  --slow-request-ms <ms>
+            Log a warning for requests that take longer than this
+            (default 500).
+  --db-lock-timeout-ms <ms>
+            How long a request waits for the database before giving up
+            with 503 Service Unavailable (default 5000).
+  --reminder-webhook <url>
+            POST a JSON payload to this URL when no entry has been
+            written for --reminder-hours. Sent once until the next entry.
+  --reminder-hours <hours>
+            How long without an entry before --reminder-webhook is
+            called (default 24).
+  --streak-notifications
+            Also call --reminder-webhook when a streak of daily entries
+            reaches 7, 30, 100 or 365 days, and when a streak breaks.
+  --print-config
+            Print the resolved configuration as JSON and exit.
 "#;
 
-fn get_parameters() -> Result<(String, IpAddr, u16), &'static str> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 4 {
+/// A maintenance command, given its arguments and returning a report to print.
+type Command = fn(Vec<String>) -> Result<String, String>;
+
+/// The `import-dir` command: add a `YYYY-MM-DD.txt` file per day as entries, in one
+/// transaction. Days that already have an entry are skipped, so it can be run again safely.
+fn import_dir(args: Vec<String>) -> Result<String, String> {
+    use chrono::{Local, TimeZone};
+
+    let (positional, cipher, tokenizer) = command_options(args)?;
+    let [dbpath, dir] = <[String; 2]>::try_from(positional).map_err(|_| USAGE.to_owned())?;
+
+    let mut files: Vec<std::path::PathBuf> = std::fs::read_dir(&dir)
+        .map_err(|e| format!("Couldn't read {}: {}", dir, e))?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file())
+        .collect();
+    files.sort();
+
+    let mut cxn = connect_and_init_db(&dbpath, cipher, tokenizer)?;
+    const EXISTS: &str = r#"
+        SELECT COUNT(*) FROM entries WHERE date = ?
+    "#;
+    const CREATE: &str = r#"
+        INSERT INTO entries (timestamp, date, body, updated_at)
+        VALUES ($1, $2, encrypt_body($3), $1)
+        RETURNING rowid
+    "#;
+    const INDEX: &str = r#"
+        INSERT INTO entrytext (rowid, body) VALUES ($1, $2)
+    "#;
+    let db_error = |e: rusqlite::Error| format!("Database error: {}", e);
+    let tx = cxn.transaction().map_err(db_error)?;
+    let (mut imported, mut existing, mut ignored) = (0, 0, Vec::new());
+    for path in files {
+        let date = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(".txt"))
+            .and_then(|stem| NaiveDate::parse_from_str(stem, "%Y-%m-%d").ok());
+        let Some(date) = date else {
+            ignored.push(format!("{} (not named YYYY-MM-DD.txt)", path.display()));
+            continue;
+        };
+        let body = match std::fs::read_to_string(&path) {
+            Ok(body) if !body.trim().is_empty() => body,
+            Ok(_) => {
+                ignored.push(format!("{} (empty)", path.display()));
+                continue;
+            }
+            Err(e) => {
+                ignored.push(format!("{} ({})", path.display(), e));
+                continue;
+            }
+        };
+        let date_str = date.format("%Y-%m-%d").to_string();
+        let count: u32 = tx
+            .query_row(EXISTS, [&date_str], |r| r.get(0))
+            .map_err(db_error)?;
+        if count > 0 {
+            existing += 1;
+            continue;
+        }
+        // Midday, so the entry's local date and its timestamp agree in any nearby timezone.
+        let timestamp = date
+            .and_hms_opt(12, 0, 0)
+            .and_then(|noon| Local.from_local_datetime(&noon).earliest())
+            .map(|noon| noon.timestamp())
+            .ok_or_else(|| format!("{} has no local midday", date_str))?;
+        let id: u32 = tx
+            .query_row(CREATE, rusqlite::params![timestamp, date_str, body], |r| {
+                r.get(0)
+            })
+            .map_err(db_error)?;
+        tx.execute(INDEX, rusqlite::params![id, body])
+            .map_err(db_error)?;
+        imported += 1;
+    }
+    record_admin_action(
+        &tx,
+        "import",
+        &format!(
+            "Imported {} entries from {}; skipped {} days that already had one",
+            imported, dir, existing
+        ),
+    )
+    .map_err(db_error)?;
+    tx.commit().map_err(db_error)?;
+
+    let mut report = format!(
+        "Imported {} entries; skipped {} days that already had one and {} other files.",
+        imported,
+        existing,
+        ignored.len()
+    );
+    for file in ignored {
+        report.push_str(&format!("\n  skipped {}", file));
+    }
+    Ok(report)
+}
+
+/// The `reindex` command: rebuild the on-disk search index from `entries`.
+fn reindex_command(args: Vec<String>) -> Result<String, String> {
+    let (positional, cipher, tokenizer) = command_options(args)?;
+    let [dbpath] = <[String; 1]>::try_from(positional).map_err(|_| USAGE.to_owned())?;
+    if cipher.is_some() {
+        return Err(
+            "Encrypted diaries are indexed in memory at startup; there's no index on disk to rebuild."
+                .to_owned(),
+        );
+    }
+    let mut cxn = connect_and_init_db(&dbpath, None, tokenizer)?;
+    let count = reindex(&mut cxn, "main", tokenizer)
+        .map_err(|e| format!("Error rebuilding search index: {:?}", e))?;
+    record_admin_action(
+        &cxn,
+        "reindex",
+        &format!(
+            "Rebuilt the search index with the {} tokenizer ({} entries) from the command line",
+            tokenizer, count
+        ),
+    )
+    .map_err(|e| format!("Error recording the reindex: {:?}", e))?;
+    Ok(format!(
+        "Indexed {} entries with the {} tokenizer.",
+        count, tokenizer
+    ))
+}
+
+/// The `check` command: look for problems that would break the diary, without changing
+/// anything. Returns an error listing them if there are any.
+fn check_command(args: Vec<String>) -> Result<String, String> {
+    use rusqlite::OpenFlags;
+
+    let (positional, cipher, _) = command_options(args)?;
+    let [dbpath] = <[String; 1]>::try_from(positional).map_err(|_| USAGE.to_owned())?;
+    let encrypted = cipher.is_some();
+    let cxn = Connection::open_with_flags(&dbpath, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .map_err(|e| format!("Couldn't open database: {:?}", e))?;
+    check_schema_version(&cxn)?;
+    let version = schema_version(&cxn)?;
+    if version < MIGRATIONS.len() {
+        return Err(format!(
+            "The database's schema version is {} rather than {}; start the server once to migrate it before checking.",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+    register_body_functions(&cxn, cipher)
+        .map_err(|e| format!("Error registering encryption functions: {:?}", e))?;
+    let db_error = |e: rusqlite::Error| format!("Database error: {}", e);
+
+    let mut problems = Vec::new();
+    let mut integrity = cxn.prepare("PRAGMA integrity_check").map_err(db_error)?;
+    for row in integrity
+        .query_map([], |r| r.get::<_, String>(0))
+        .map_err(db_error)?
+    {
+        match row.map_err(db_error)? {
+            ok if ok == "ok" => {}
+            msg => problems.push(format!("Integrity check: {}", msg)),
+        }
+    }
+
+    let entries: u32 = cxn
+        .query_row("SELECT COUNT(*) FROM entries", [], |r| r.get(0))
+        .map_err(db_error)?;
+    let indexed: u32 = cxn
+        .query_row("SELECT COUNT(*) FROM entrytext", [], |r| r.get(0))
+        .map_err(db_error)?;
+    // Encrypted diaries are indexed in memory, so their on-disk index should be empty.
+    let expected = if encrypted { 0 } else { entries };
+    if indexed != expected {
+        problems.push(format!(
+            "The search index has {} entries but should have {}; run the reindex command",
+            indexed, expected
+        ));
+    }
+
+    // Bodies are decrypted one at a time so an entry that can't be doesn't stop the check.
+    const ENTRIES: &str = r#"
+        SELECT rowid, date, timestamp, body, mood, updated_at FROM entries
+    "#;
+    const DECRYPT: &str = r#"
+        SELECT decrypt_body(body) FROM entries WHERE rowid = ?
+    "#;
+    let mut qry = cxn.prepare(ENTRIES).map_err(db_error)?;
+    let mut ids = Vec::new();
+    for (n, row) in qry
+        .query_map([], RawEntry::from_row)
+        .map_err(db_error)?
+        .enumerate()
+    {
+        let raw = match row {
+            Ok(raw) => raw,
+            Err(e) => {
+                problems.push(format!("Row {} of entries can't be read: {}", n + 1, e));
+                continue;
+            }
+        };
+        let (id, date, timestamp) = (raw.id, raw.date.clone(), raw.timestamp);
+        let entry: Result<Entry, AppError> = raw.try_into();
+        if let Err((_, msg)) = entry {
+            problems.push(format!(
+                "Entry {}: {} (date {:?}, timestamp {})",
+                id, msg, date, timestamp
+            ));
+        }
+        ids.push(id);
+    }
+    for id in ids {
+        if let Err(e) = cxn.query_row(DECRYPT, [id], |r| r.get::<_, String>(0)) {
+            problems.push(format!("Entry {}: {}", id, e));
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(format!("No problems found in {} entries.", entries))
+    } else {
+        Err(format!(
+            "Found {} problems:\n  {}",
+            problems.len(),
+            problems.join("\n  ")
+        ))
+    }
+}
+
+/// Split a maintenance command's arguments into positional ones and the database options
+/// it shares with the server.
+fn command_options(args: Vec<String>) -> Result<(Vec<String>, Option<Cipher>, Tokenizer), String> {
+    let mut positional = Vec::new();
+    let mut cipher = None;
+    let mut tokenizer = Tokenizer::default();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--encryption-key-file" => {
+                cipher = Some(load_cipher(&args.next().ok_or(USAGE)?)?);
+            }
+            "--fts-tokenizer" => {
+                tokenizer = args
+                    .next()
+                    .as_deref()
+                    .and_then(Tokenizer::parse)
+                    .ok_or(USAGE)?
+            }
+            _ if arg.starts_with("--") => return Err(USAGE.to_owned()),
+            _ => positional.push(arg),
+        }
+    }
+    Ok((positional, cipher, tokenizer))
+}
+
+#[derive(serde::Serialize)]
+struct DiaryConfig {
+    name: String,
+    dbpath: String,
+}
+
+#[derive(serde::Serialize)]
+struct Config {
+    diaries: Vec<DiaryConfig>,
+    host: IpAddr,
+    port: u16,
+    encryption_key_file: Option<String>,
+    fts_tokenizer: Tokenizer,
+    trust_html: bool,
+    smart_punctuation: bool,
+    math: bool,
+    site_title: String,
+    author: Option<String>,
+    read_only: bool,
+    slow_request_ms: u64,
+    db_lock_timeout_ms: u64,
+    duplicate_window_secs: u32,
+    idempotency_window_secs: u32,
+    entries_per_month: usize,
+    index_preview_length: usize,
+    prompts_file: Option<String>,
+    base_path: String,
+    max_query_length: usize,
+    max_search_results: u32,
+    snippet_start: String,
+    snippet_end: String,
+    snippet_ellipsis: String,
+    locale: Locale,
+    week_start: chrono::Weekday,
+    reminder_webhook: Option<String>,
+    reminder_hours: u32,
+    streak_notifications: bool,
+    /// Not configurable, but printed with `--print-config` to help diagnose deployments.
+    recent_entries: usize,
+    #[serde(skip)]
+    print_config: bool,
+}
+
+fn get_parameters() -> Result<Config, &'static str> {
+    let mut positional = Vec::new();
+    let mut encryption_key_file = None;
+    let mut fts_tokenizer = Tokenizer::default();
+    let mut trust_html = false;
+    let mut smart_punctuation = true;
+    let mut math = false;
+    let mut site_title = String::from(DEFAULT_SITE_TITLE);
+    let mut author = None;
+    let mut read_only = false;
+    let mut slow_request_ms = DEFAULT_SLOW_REQUEST_MS;
+    let mut db_lock_timeout_ms = DEFAULT_DB_LOCK_TIMEOUT_MS;
+    let mut duplicate_window_secs = DEFAULT_DUPLICATE_WINDOW_SECS;
+    let mut idempotency_window_secs = DEFAULT_IDEMPOTENCY_WINDOW_SECS;
+    let mut entries_per_month = DEFAULT_ENTRIES_PER_MONTH;
+    let mut index_preview_length = DEFAULT_INDEX_PREVIEW_LENGTH;
+    let mut prompts_file = None;
+    let mut base_path = String::new();
+    let mut max_query_length = DEFAULT_MAX_QUERY_LENGTH;
+    let mut max_search_results = DEFAULT_MAX_SEARCH_RESULTS;
+    let mut snippet = SnippetOptions::default();
+    let mut locale = Locale::default();
+    let mut week_start = chrono::Weekday::Mon;
+    let mut reminder_webhook = None;
+    let mut reminder_hours = DEFAULT_REMINDER_HOURS;
+    let mut streak_notifications = false;
+    let mut print_config = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--encryption-key-file" => encryption_key_file = Some(args.next().ok_or(USAGE)?),
+            "--fts-tokenizer" => {
+                fts_tokenizer = args
+                    .next()
+                    .as_deref()
+                    .and_then(Tokenizer::parse)
+                    .ok_or(USAGE)?
+            }
+            "--trust-html" => trust_html = true,
+            "--no-smart-punctuation" => smart_punctuation = false,
+            "--math" => math = true,
+            "--site-title" => site_title = args.next().ok_or(USAGE)?,
+            "--author" => author = Some(args.next().ok_or(USAGE)?),
+            "--read-only" => read_only = true,
+            "--duplicate-window-secs" => {
+                duplicate_window_secs = args
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .ok_or(USAGE)?
+            }
+            "--idempotency-window-secs" => {
+                idempotency_window_secs = args
+                    .next()
+                    .and_then(|secs| secs.parse().ok())
+                    .ok_or(USAGE)?
+            }
+            "--entries-per-month" => {
+                entries_per_month = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?
+            }
+            "--index-preview-length" => {
+                index_preview_length = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?
+            }
+            "--prompts-file" => prompts_file = Some(args.next().ok_or(USAGE)?),
+            "--max-query-length" => {
+                max_query_length = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?
+            }
+            "--max-search-results" => {
+                max_search_results = args.next().and_then(|n| n.parse().ok()).ok_or(USAGE)?
+            }
+            "--snippet-start" => snippet.start = args.next().ok_or(USAGE)?,
+            "--snippet-end" => snippet.end = args.next().ok_or(USAGE)?,
+            "--snippet-ellipsis" => snippet.ellipsis = args.next().ok_or(USAGE)?,
+            "--locale" => {
+                locale = args
+                    .next()
+                    .as_deref()
+                    .and_then(Locale::parse)
+                    .ok_or(USAGE)?
+            }
+            "--week-start" => {
+                week_start = match args.next().ok_or(USAGE)?.as_str() {
+                    "monday" => chrono::Weekday::Mon,
+                    "sunday" => chrono::Weekday::Sun,
+                    _ => return Err(USAGE),
+                }
+            }
+            "--base-path" => base_path = parse_base_path(&args.next().ok_or(USAGE)?)?,
+            "--slow-request-ms" => {
+                slow_request_ms = args.next().and_then(|ms| ms.parse().ok()).ok_or(USAGE)?
+            }
+            "--db-lock-timeout-ms" => {
+                db_lock_timeout_ms = args.next().and_then(|ms| ms.parse().ok()).ok_or(USAGE)?
+            }
+            "--reminder-webhook" => {
+                let url = args.next().ok_or(USAGE)?;
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(USAGE);
+                }
+                reminder_webhook = Some(url)
+            }
+            "--reminder-hours" => {
+                reminder_hours = args
+                    .next()
+                    .and_then(|hours| hours.parse().ok())
+                    .filter(|&hours| hours > 0)
+                    .ok_or(USAGE)?
+            }
+            "--streak-notifications" => streak_notifications = true,
+            "--print-config" => print_config = true,
+            _ if arg.starts_with("--") => return Err(USAGE),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 3 || (streak_notifications && reminder_webhook.is_none()) {
         return Err(USAGE);
     }
-    let dbpath = args[1].clone();
-    let host = match args[2].parse() {
+    let diaries = parse_diaries(&positional[0])?;
+    let host = match positional[1].parse() {
         Ok(host) => host,
         _ => return Err(USAGE),
     };
-    let port = match args[3].parse() {
+    let port = match positional[2].parse() {
         Ok(port) => port,
         _ => return Err(USAGE),
     };
-    Ok((dbpath, host, port))
+    Ok(Config {
+        diaries,
+        host,
+        port,
+        encryption_key_file,
+        fts_tokenizer,
+        trust_html,
+        smart_punctuation,
+        math,
+        site_title,
+        author,
+        read_only,
+        slow_request_ms,
+        db_lock_timeout_ms,
+        duplicate_window_secs,
+        idempotency_window_secs,
+        entries_per_month,
+        index_preview_length,
+        prompts_file,
+        base_path,
+        max_query_length,
+        max_search_results,
+        snippet_start: snippet.start,
+        snippet_end: snippet.end,
+        snippet_ellipsis: snippet.ellipsis,
+        locale,
+        week_start,
+        reminder_webhook,
+        reminder_hours,
+        streak_notifications,
+        recent_entries: RECENT_ENTRIES,
+        print_config,
+    })
+}
+
+/// Normalize a base path to start with a slash and not end with one, so it can be put in
+/// front of the app's paths. The root is the empty string.
+fn parse_base_path(arg: &str) -> Result<String, &'static str> {
+    let path = arg.trim_matches('/');
+    if path.is_empty() {
+        return Ok(String::new());
+    }
+    let valid = path.split('/').all(|segment| {
+        !segment.is_empty()
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-_.~".contains(c))
+    });
+    if !valid {
+        return Err(USAGE);
+    }
+    Ok(format!("/{}", path))
+}
+
+fn parse_diaries(arg: &str) -> Result<Vec<DiaryConfig>, &'static str> {
+    if !arg.contains('=') {
+        let diary = DiaryConfig {
+            name: String::from("diary"),
+            dbpath: arg.to_owned(),
+        };
+        return Ok(vec![diary]);
+    }
+    let mut diaries: Vec<DiaryConfig> = Vec::new();
+    for pair in arg.split(',') {
+        let (name, dbpath) = pair.split_once('=').ok_or(USAGE)?;
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name || dbpath.is_empty() || diaries.iter().any(|d| d.name == name) {
+            return Err(USAGE);
+        }
+        diaries.push(DiaryConfig {
+            name: name.to_owned(),
+            dbpath: dbpath.to_owned(),
+        });
+    }
+    Ok(diaries)
 }
 
-fn connect_and_init_db(dbpath: &str) -> Result<rusqlite::Connection, String> {
-    let cxn = rusqlite::Connection::open(dbpath)
+fn connect_and_init_db(
+    dbpath: &str,
+    cipher: Option<Cipher>,
+    tokenizer: Tokenizer,
+) -> Result<rusqlite::Connection, String> {
+    let mut cxn = rusqlite::Connection::open(dbpath)
         .map_err(|e| format!("Couldn't open database: {:?}", e))?;
-    let init_statements = vec![
-        r##"
+    check_schema_version(&cxn)?;
+    // SQLite quietly opens files it can't write to read-only. They can still be served, as
+    // long as nothing needs writing to get started.
+    let read_only = is_read_only(&cxn)?;
+    if read_only {
+        let version = schema_version(&cxn)?;
+        if version < MIGRATIONS.len() {
+            return Err(format!(
+                "the database file is read-only, but its schema (version {}) needs upgrading \
+                 to version {}; make it writable and start the server once",
+                version,
+                MIGRATIONS.len()
+            ));
+        }
+        if cipher.is_none() && indexed_tokenizer(&cxn, "main")? != Some(tokenizer) {
+            return Err(format!(
+                "the database file is read-only, but its search index needs rebuilding with \
+                 the {} tokenizer; make it writable or use the tokenizer it was built with",
+                tokenizer
+            ));
+        }
+        warn!(
+            "Database {} is read-only; serving it without allowing changes",
+            dbpath
+        );
+    }
+    let init_statements = if read_only {
+        vec![]
+    } else {
+        vec![
+            r##"
             CREATE TABLE IF NOT EXISTS entries
             (
                 timestamp INTEGER NOT NULL,
@@ -75,513 +788,6169 @@ fn connect_and_init_db(dbpath: &str) -> Result<rusqlite::Connection, String> {
                 body TEXT NOT NULL
             )
         "##,
-        r##"
+            r##"
             CREATE VIRTUAL TABLE IF NOT EXISTS entrytext
                 USING fts5(body)
         "##,
-        r##"
+            r##"
             CREATE TABLE IF NOT EXISTS draft
             (
                 draft TEXT NOT NULL
             )
         "##,
-    ];
+        ]
+    };
     for stmt in init_statements {
         cxn.execute(stmt, [])
             .map_err(|e| format!("Error initializing database: {:?}", e))?;
     }
+    migrate_db(&mut cxn)?;
+    let encrypted = cipher.is_some();
+    register_body_functions(&cxn, cipher)
+        .map_err(|e| format!("Error registering encryption functions: {:?}", e))?;
+    if encrypted {
+        index_in_memory(&mut cxn, tokenizer)?;
+    } else if indexed_tokenizer(&cxn, "main")? != Some(tokenizer) {
+        info!(
+            "Rebuilding the search index with the {} tokenizer",
+            tokenizer
+        );
+        let count = reindex(&mut cxn, "main", tokenizer)
+            .map_err(|e| format!("Error rebuilding search index: {:?}", e))?;
+        info!("Indexed {} entries", count);
+        record_admin_action(
+            &cxn,
+            "reindex",
+            &format!(
+                "Rebuilt the search index with the {} tokenizer ({} entries) at startup",
+                tokenizer, count
+            ),
+        )
+        .map_err(|e| format!("Error recording the reindex: {:?}", e))?;
+    }
+    // Trigrams aren't words, so there's nothing to suggest from that index's vocabulary.
+    if tokenizer != Tokenizer::Trigram {
+        let schema = if encrypted { "temp" } else { "main" };
+        cxn.execute_batch(&format!(
+            "CREATE VIRTUAL TABLE temp.entrytext_vocab USING fts5vocab({}, entrytext, 'row')",
+            schema
+        ))
+        .map_err(|e| format!("Error creating search vocabulary: {:?}", e))?;
+    }
     Ok(cxn)
 }
 
-fn newapp(cxn: rusqlite::Connection) -> axum::Router {
-    use axum::routing::{get, get_service, post, Router};
-    use tower_http::services::ServeDir;
-    use tower_http::trace::TraceLayer;
-
-    let cxn_arcmut = Arc::new(Mutex::new(cxn));
-
-    Router::new()
-        .route("/", get(get_index))
-        .route("/new", get(get_new_entry).post(post_new_entry))
-        .route("/draft", post(post_draft))
-        .route("/entry/:rowid", get(get_entry))
-        .route("/year/:year", get(get_year))
-        .route("/search", get(get_search))
-        .nest_service(
-            "/static",
-            get_service(ServeDir::new("./static/").precompressed_br()),
-        )
-        .layer(TraceLayer::new_for_http())
-        .layer(Extension(cxn_arcmut))
+/// Whether the connection's database can't be written to, e.g. because it's on a
+/// read-only mount.
+fn is_read_only(cxn: &Connection) -> Result<bool, String> {
+    cxn.is_readonly(rusqlite::DatabaseName::Main)
+        .map_err(|e| format!("Couldn't check whether the database is writable: {:?}", e))
 }
 
-pub(crate) type AppError = (StatusCode, String);
+/// How the full-text index splits entries into searchable terms.
+///
+/// Changing tokenizers means rebuilding the index, which happens at startup when the
+/// configured tokenizer differs from the one the index was built with.
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Tokenizer {
+    /// FTS5's default: case- and diacritic-insensitive whole words. Small and fast, but
+    /// "walk" won't find "walked".
+    #[default]
+    Unicode61,
+    /// Porter stemming over `unicode61`, so "walk" also finds "walking" and "walked". Only
+    /// stems English, and can conflate unrelated words with a common stem.
+    Porter,
+    /// Indexes every three-character sequence, so any substring of three or more
+    /// characters matches (e.g. "ound" finds "found"). Much larger index, no stemming, and
+    /// shorter queries match nothing.
+    Trigram,
+}
 
-type Response = Result<Html<String>, AppError>;
+impl Tokenizer {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "unicode61" => Some(Tokenizer::Unicode61),
+            "porter" => Some(Tokenizer::Porter),
+            "trigram" => Some(Tokenizer::Trigram),
+            _ => None,
+        }
+    }
 
-struct Entry {
-    id: u32,
-    date: NaiveDate,
-    timestamp: DateTime<Utc>,
-    body: String,
+    /// The FTS5 `tokenize` option for this tokenizer.
+    fn spec(self) -> &'static str {
+        match self {
+            Tokenizer::Unicode61 => "unicode61",
+            Tokenizer::Porter => "porter unicode61",
+            Tokenizer::Trigram => "trigram",
+        }
+    }
 }
 
-impl Entry {
-    fn try_fetch(cxn: &mut rusqlite::Connection, id: u32) -> Result<Self, AppError> {
-        const QUERY: &str = r#"
-            SELECT rowid, date, timestamp, body
-            FROM entries
-            WHERE rowid = ?
-        "#;
-        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
-        let entry = qry
-            .query_row([&id], RawEntry::from_row)
-            .map_err(convert_db_error)?
-            .try_into()?;
-        Ok(entry)
+impl std::fmt::Display for Tokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.spec())
     }
 }
 
-struct RawEntry {
-    id: u32,
-    date: String,
-    timestamp: u64,
-    body: String,
+/// The tokenizer `schema.entrytext` was created with, if it exists.
+fn indexed_tokenizer(cxn: &Connection, schema: &str) -> Result<Option<Tokenizer>, String> {
+    let qry = format!(
+        "SELECT sql FROM {}.sqlite_master WHERE name = 'entrytext'",
+        schema
+    );
+    let sql: Option<String> = cxn
+        .query_row(&qry, [], |r| r.get(0))
+        .optional()
+        .map_err(|e| format!("Error reading search index schema: {:?}", e))?;
+    let tokenizer = sql.map(|sql| {
+        let spec = sql
+            .split_once("tokenize = '")
+            .and_then(|(_, rest)| rest.split_once('\''))
+            .map(|(spec, _)| spec);
+        match spec {
+            None => Tokenizer::Unicode61,
+            Some(spec) => [Tokenizer::Unicode61, Tokenizer::Porter, Tokenizer::Trigram]
+                .into_iter()
+                .find(|t| t.spec() == spec)
+                .unwrap_or_default(),
+        }
+    });
+    Ok(tokenizer)
 }
 
-impl RawEntry {
-    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
-        let entry = RawEntry {
-            id: r.get(0)?,
-            date: r.get(1)?,
-            timestamp: r.get(2)?,
-            body: r.get(3)?,
-        };
+/// Drop and rebuild `schema.entrytext` from `entries` using `tokenizer`, returning how many
+/// entries were indexed.
+fn reindex(cxn: &mut Connection, schema: &str, tokenizer: Tokenizer) -> rusqlite::Result<usize> {
+    let tx = cxn.transaction()?;
+    tx.execute_batch(&format!(
+        "DROP TABLE IF EXISTS {schema}.entrytext;
+         CREATE VIRTUAL TABLE {schema}.entrytext USING fts5(body, tokenize = '{spec}');",
+        schema = schema,
+        spec = tokenizer.spec(),
+    ))?;
+    let count = tx.execute(
+        &format!(
+            "INSERT INTO {}.entrytext (rowid, body) SELECT rowid, decrypt_body(body) FROM entries",
+            schema
+        ),
+        [],
+    )?;
+    tx.commit()?;
+    Ok(count)
+}
 
-        Ok(entry)
+/// Read journaling prompts, one per line, skipping blank lines.
+fn load_prompts(path: &str) -> Result<Vec<String>, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read prompts file {}: {}", path, e))?;
+    let prompts: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect();
+    if prompts.is_empty() {
+        return Err(format!("Prompts file {} has no prompts", path));
     }
+    Ok(prompts)
 }
 
-impl TryInto<Entry> for RawEntry {
-    type Error = AppError;
-    fn try_into(self) -> Result<Entry, Self::Error> {
-        use chrono::{LocalResult, TimeZone};
+type Cipher = Arc<chacha20poly1305::XChaCha20Poly1305>;
 
-        let timestamp = match Utc.timestamp_opt(self.timestamp as i64, 0) {
-            LocalResult::None | LocalResult::Ambiguous(_, _) => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("Invalid timestamp: {}", self.timestamp),
-                ))
-            }
-            LocalResult::Single(t) => t,
-        };
+/// Marks bodies that were encrypted before being stored; anything else is plaintext.
+const ENCRYPTED_PREFIX: &str = "enc1:";
 
-        let entry = Entry {
-            id: self.id,
-            date: NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").map_err(convert_parse_error)?,
-            timestamp,
-            body: self.body,
-        };
-        Ok(entry)
-    }
-}
+fn load_cipher(path: &str) -> Result<Cipher, String> {
+    use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
 
-fn convert_db_error(err: rusqlite::Error) -> AppError {
-    use rusqlite::Error;
-    error!("{:?}", err);
-    match err {
-        Error::QueryReturnedNoRows => (StatusCode::NOT_FOUND, "Not found".to_owned()),
-        _ => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Database Error".to_owned(),
-        ),
+    let hex = std::fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read encryption key file {}: {}", path, e))?;
+    let hex = hex.trim();
+    let key: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect();
+    match key {
+        Some(key) if key.len() == 32 => Ok(Arc::new(XChaCha20Poly1305::new(
+            chacha20poly1305::Key::from_slice(&key),
+        ))),
+        _ => Err(format!(
+            "Encryption key file {} must contain 64 hex digits",
+            path
+        )),
     }
 }
 
-fn convert_parse_error(err: chrono::ParseError) -> AppError {
-    error!("{:?}", err);
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        "Date format conversion error".to_owned(),
-    )
-}
+/// Register `encrypt_body` and `decrypt_body` SQL functions, which every query that stores or
+/// reads an entry or draft body goes through.
+///
+/// Without a cipher, `encrypt_body` stores plaintext and `decrypt_body` passes plaintext
+/// through, so unencrypted databases keep working. With one, plaintext bodies written before
+/// encryption was enabled are still readable.
+fn register_body_functions(cxn: &Connection, cipher: Option<Cipher>) -> rusqlite::Result<()> {
+    use rusqlite::functions::FunctionFlags;
 
-fn convert_render_error(err: askama::Error) -> AppError {
-    error!("rendering new entry: {:?}", err);
-    (
-        StatusCode::INTERNAL_SERVER_ERROR,
-        "Template rendering error".to_owned(),
+    let encrypt_cipher = cipher.clone();
+    cxn.create_scalar_function("encrypt_body", 1, FunctionFlags::SQLITE_UTF8, move |ctx| {
+        let body: String = ctx.get(0)?;
+        match &encrypt_cipher {
+            Some(cipher) => encrypt_body(cipher, &body),
+            None => Ok(body),
+        }
+    })?;
+    cxn.create_scalar_function(
+        "decrypt_body",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let body: String = ctx.get(0)?;
+            match (body.strip_prefix(ENCRYPTED_PREFIX), &cipher) {
+                (None, _) => Ok(body),
+                (Some(encrypted), Some(cipher)) => decrypt_body(cipher, encrypted),
+                (Some(_), None) => Err(body_function_error(
+                    "Found an encrypted body but no encryption key is configured",
+                )),
+            }
+        },
     )
 }
 
-#[derive(Template)]
-#[template(path = "index.html")]
-struct IndexViewModel {
-    recent: Vec<Entry>,
-    year_counts: Vec<(u32, u32)>,
+fn encrypt_body(cipher: &Cipher, body: &str) -> rusqlite::Result<String> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+
+    let nonce = chacha20poly1305::XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let mut sealed = nonce.to_vec();
+    let ciphertext = cipher
+        .encrypt(&nonce, body.as_bytes())
+        .map_err(|_| body_function_error("Couldn't encrypt body"))?;
+    sealed.extend(ciphertext);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, STANDARD.encode(sealed)))
 }
 
-impl Entry {
-    fn recent(cxn: &mut rusqlite::Connection, count: usize) -> Result<Vec<Entry>, AppError> {
-        const QUERY: &str = r#"
-            SELECT rowid, date, timestamp, body
-            FROM entries
-            ORDER BY timestamp DESC
-            LIMIT ?
-        "#;
-        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
-        let mut entries = Vec::new();
-        let results = qry
-            .query_map([count], RawEntry::from_row)
-            .map_err(convert_db_error)?;
-        for raw in results {
-            let raw = raw.map_err(convert_db_error)?;
-            let entry = raw.try_into()?;
-            entries.push(entry);
-        }
-        Ok(entries)
+fn decrypt_body(cipher: &Cipher, encrypted: &str) -> rusqlite::Result<String> {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use chacha20poly1305::{aead::Aead, XNonce};
+
+    const NONCE_LEN: usize = 24;
+    let sealed = STANDARD
+        .decode(encrypted)
+        .map_err(|_| body_function_error("Encrypted body isn't valid base64"))?;
+    if sealed.len() < NONCE_LEN {
+        return Err(body_function_error("Encrypted body is truncated"));
     }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    let plaintext = cipher
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| body_function_error("Couldn't decrypt body; is the key correct?"))?;
+    String::from_utf8(plaintext).map_err(|_| body_function_error("Decrypted body isn't UTF-8"))
 }
 
-type ConnectionArcMux = Arc<Mutex<rusqlite::Connection>>;
+fn body_function_error(msg: &str) -> rusqlite::Error {
+    rusqlite::Error::UserFunctionError(msg.into())
+}
 
-fn lock_db(
-    cxn_arcmux: &ConnectionArcMux,
-) -> std::result::Result<std::sync::MutexGuard<rusqlite::Connection>, AppError> {
-    cxn_arcmux.lock().map_err(|e| {
+/// Build a search index of decrypted bodies that only exists in memory.
+///
+/// `temp.entrytext` shadows the on-disk `entrytext` for unqualified table names, so the
+/// search and insert queries use it without changes and no plaintext is written to disk.
+fn index_in_memory(cxn: &mut Connection, tokenizer: Tokenizer) -> Result<(), String> {
+    cxn.execute_batch("PRAGMA temp_store = MEMORY")
+        .and_then(|_| reindex(cxn, "temp", tokenizer))
+        .map_err(|e| format!("Error building in-memory search index: {:?}", e))?;
+    let on_disk: u32 = cxn
+        .query_row("SELECT COUNT(*) FROM main.entrytext", [], |r| r.get(0))
+        .map_err(|e| format!("Error checking search index: {:?}", e))?;
+    if on_disk > 0 {
+        warn!(
+            "The on-disk search index still holds {} plaintext entries from before encryption was enabled",
+            on_disk
+        );
+    }
+    Ok(())
+}
+
+/// Schema changes made after the initial tables were created, in the order they were
+/// introduced. The database's `user_version` records how many have been applied, so new
+/// migrations must only ever be appended.
+const MIGRATIONS: &[&str] = &[
+    r##"
+        ALTER TABLE draft ADD COLUMN saved_at INTEGER
+    "##,
+    r##"
+        ALTER TABLE entries ADD COLUMN mood TEXT
+    "##,
+    r##"
+        ALTER TABLE entries ADD COLUMN updated_at INTEGER
+    "##,
+    r##"
+        UPDATE entries SET updated_at = timestamp
+    "##,
+    r##"
+        CREATE TABLE idempotency_keys
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Couldn't lock the item repo: {:?}", e),
+            key TEXT PRIMARY KEY,
+            entry_id INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
         )
-    })
-}
+    "##,
+    r##"
+        CREATE TABLE history
+        (
+            entry_id INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            body TEXT NOT NULL,
+            mood TEXT,
+            created_at INTEGER NOT NULL
+        )
+    "##,
+    r##"
+        CREATE TABLE revisions
+        (
+            entry_id INTEGER NOT NULL,
+            body TEXT NOT NULL,
+            edited_at INTEGER NOT NULL
+        )
+    "##,
+    r##"
+        ALTER TABLE entries ADD COLUMN locked INTEGER NOT NULL DEFAULT 0
+    "##,
+    r##"
+        CREATE TABLE admin_log
+        (
+            action TEXT NOT NULL,
+            summary TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )
+    "##,
+    r##"
+        ALTER TABLE draft ADD COLUMN session TEXT
+    "##,
+    // Rows inserted without an `updated_at` (e.g. by imprt.py) after it was first
+    // backfilled, and any inserted like that from now on.
+    r##"
+        UPDATE entries SET updated_at = timestamp WHERE updated_at IS NULL
+    "##,
+    r##"
+        CREATE TRIGGER entries_updated_at AFTER INSERT ON entries
+        WHEN NEW.updated_at IS NULL
+        BEGIN
+            UPDATE entries SET updated_at = NEW.timestamp WHERE rowid = NEW.rowid;
+        END
+    "##,
+    r##"
+        CREATE TABLE deleted_entries
+        (
+            entry_id INTEGER NOT NULL,
+            date TEXT NOT NULL,
+            deleted_at INTEGER NOT NULL
+        )
+    "##,
+    // One draft per session; the empty session is for drafts saved without one.
+    r##"
+        UPDATE draft SET session = '' WHERE session IS NULL
+    "##,
+    r##"
+        CREATE UNIQUE INDEX draft_session ON draft (session)
+    "##,
+];
 
-async fn get_index(Extension(cxn_arcmux): Extension<ConnectionArcMux>) -> Response {
-    let mut cxn = lock_db(&cxn_arcmux)?;
-    let recent = Entry::recent(&mut cxn, 8)?;
-    let year_counts = year_counts(&mut cxn)?;
-    let vm = IndexViewModel {
-        recent,
-        year_counts,
-    };
-    let body = vm.render().map_err(convert_render_error)?;
-    Ok(Html::from(body))
+fn schema_version(cxn: &rusqlite::Connection) -> Result<usize, String> {
+    cxn.query_row("PRAGMA user_version", [], |r| r.get(0))
+        .map_err(|e| format!("Couldn't read schema version: {:?}", e))
 }
 
-#[derive(Template)]
-#[template(path = "new.html")]
-struct NewEntryViewModel {
-    draft: String,
+/// Refuse to touch a database that a newer version of the app has migrated, since this
+/// version doesn't know what its schema looks like.
+fn check_schema_version(cxn: &rusqlite::Connection) -> Result<(), String> {
+    let version = schema_version(cxn)?;
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "the database's schema version is {} but this version of web-diary-rs only \
+             supports up to {}; upgrade web-diary-rs to use it",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+    Ok(())
 }
 
-async fn get_new_entry(Extension(cxn_arcmux): Extension<ConnectionArcMux>) -> Response {
-    let mut cxn = lock_db(&cxn_arcmux)?;
-    let draft = get_draft(&mut cxn)?.unwrap_or_else(String::new);
-    let vm = NewEntryViewModel { draft };
-    vm.render().map_err(convert_render_error).map(Html::from)
+fn migrate_db(cxn: &mut rusqlite::Connection) -> Result<(), String> {
+    let version = schema_version(cxn)?;
+    if version >= MIGRATIONS.len() {
+        return Ok(());
+    }
+    info!(
+        "Migrating database from version {} to {}",
+        version,
+        MIGRATIONS.len()
+    );
+    let tx = cxn
+        .transaction()
+        .map_err(|e| format!("Error starting migration: {:?}", e))?;
+    for stmt in &MIGRATIONS[version..] {
+        tx.execute(stmt, [])
+            .map_err(|e| format!("Error migrating database: {:?}", e))?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())
+        .map_err(|e| format!("Error updating schema version: {:?}", e))?;
+    record_admin_action(
+        &tx,
+        "migrate",
+        &format!(
+            "Migrated the schema from version {} to {}",
+            version,
+            MIGRATIONS.len()
+        ),
+    )
+    .map_err(|e| format!("Error recording the migration: {:?}", e))?;
+    tx.commit()
+        .map_err(|e| format!("Error committing migration: {:?}", e))
 }
 
-#[derive(serde::Deserialize)]
-struct NewEntry {
-    body: String,
-}
+/// Directory (relative to the working directory) that static assets are served from.
+///
+/// Templates are compiled into the binary by askama, so this is the only part of the app
+/// that depends on the working directory.
+const STATIC_DIR: &str = "./static/";
 
-async fn post_new_entry(
-    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
-    Form(newentry): Form<NewEntry>,
-) -> Result<Redirect, AppError> {
-    let mut cxn = lock_db(&cxn_arcmux)?;
-    const CREATE: &str = r#"
-        INSERT INTO entries (timestamp, date, body)
-        VALUES (unixepoch('now'), date('now', 'localtime'), $1)
-        RETURNING rowid
-    "#;
-    const INDEX: &str = r#"
-        INSERT INTO entrytext (body) VALUES ($1)
-    "#;
-    let new_entry_id: u32 = cxn
-        .query_row(CREATE, [&newentry.body], |r| r.get(0))
-        .map_err(convert_db_error)?;
-    cxn.execute(INDEX, [&newentry.body])
-        .map_err(convert_db_error)?;
-    clear_draft(&mut cxn)?;
-    let new_item_url = format!("/entry/{}", new_entry_id);
-    Ok(Redirect::to(&new_item_url))
+/// A diary's configuration. Diaries whose files can't be written to are read-only, even
+/// without `--read-only`.
+fn diary_site(prefix: String, options: &SiteOptions, cxn: &Connection) -> SiteConfig {
+    let mut site = SiteConfig::new(prefix, options.clone());
+    site.read_only |= is_read_only(cxn).unwrap_or(false);
+    site
 }
 
-#[derive(Template)]
-#[template(path = "entry.html")]
-struct EntryViewModel {
-    date: NaiveDate,
-    timestamp: DateTime<Utc>,
-    body: String,
+/// Build the app for one or more named diaries.
+///
+/// A single diary is served from the root; several are each nested under `/d/<name>/`,
+/// with an index of diaries at the root.
+fn newapp(
+    mut diaries: Vec<(String, rusqlite::Connection)>,
+    mut options: SiteOptions,
+    slow_request: std::time::Duration,
+) -> axum::Router {
+    use axum::http::{header::CACHE_CONTROL, HeaderValue, Response};
+    use axum::middleware::{from_fn, map_response};
+    use axum::routing::{get, get_service, Router};
+    use tower_http::services::ServeDir;
+    use tower_http::set_header::SetResponseHeaderLayer;
+    use tower_http::trace::TraceLayer;
+
+    if !std::path::Path::new(STATIC_DIR).is_dir() {
+        warn!(
+            "Static directory {} not found; pages will render without styles or scripts",
+            STATIC_DIR
+        );
+    }
+    options.asset_version = asset_version(STATIC_DIR);
+
+    let app = if diaries.len() == 1 {
+        let (_, cxn) = diaries.remove(0);
+        let site = diary_site(String::new(), &options, &cxn);
+        diary_routes(cxn, site)
+    } else {
+        let names: Vec<String> = diaries.iter().map(|(name, _)| name.clone()).collect();
+        let root = Arc::new(SiteConfig::new(String::new(), options.clone()));
+        let mut app = Router::new().route(
+            "/",
+            get(move |site: Site| get_diaries(site, names.clone())).layer(Extension(root)),
+        );
+        for (name, cxn) in diaries {
+            let prefix = format!("/d/{}", name);
+            let site = diary_site(prefix.clone(), &options, &cxn);
+            let index = site.prefix.clone();
+            app = app.nest(&prefix, diary_routes(cxn, site)).route(
+                &format!("{}/", prefix),
+                get(move || async move { Redirect::permanent(&index) }),
+            );
+        }
+        app
+    };
+
+    let errors = RecentErrors::default();
+    let root = Arc::new(SiteConfig::new(String::new(), options.clone()));
+    let app = app
+        .route(
+            "/admin/errors",
+            get(get_recent_errors).layer(Extension(root)),
+        )
+        .layer(Extension(errors.clone()));
+
+    let app = app.nest_service(
+        "/static",
+        get_service(ServeDir::new(STATIC_DIR).precompressed_br()).layer(
+            // Asset URLs change with their contents, so a cached copy never goes stale.
+            SetResponseHeaderLayer::overriding(CACHE_CONTROL, |res: &Response<_>| {
+                res.status()
+                    .is_success()
+                    .then_some(HeaderValue::from_static(
+                        "public, max-age=31536000, immutable",
+                    ))
+            }),
+        ),
+    );
+    let app = if options.base_path.is_empty() {
+        app
+    } else {
+        let index = options.base_path.clone();
+        Router::new().nest(&options.base_path, app).route(
+            &format!("{}/", options.base_path),
+            get(move || async move { Redirect::permanent(&index) }),
+        )
+    };
+
+    app.layer(map_response(remember_theme))
+        .layer(from_fn(move |req, next| {
+            record_server_error(errors.clone(), req, next)
+        }))
+        .layer(from_fn(move |req, next| {
+            log_slow_request(slow_request, req, next)
+        }))
+        .layer(TraceLayer::new_for_http())
 }
 
-impl From<Entry> for EntryViewModel {
-    fn from(entry: Entry) -> Self {
-        EntryViewModel {
-            date: entry.date,
-            timestamp: entry.timestamp,
-            body: entry.body,
+/// A short hash of everything in `dir`, which changes whenever an asset does. Computed once
+/// at startup, so assets changed while the server runs need a restart to be picked up.
+fn asset_version(dir: &str) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut paths: Vec<_> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => return String::new(),
+    };
+    paths.sort();
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    for path in paths {
+        if let Ok(contents) = std::fs::read(&path) {
+            path.hash(&mut hasher);
+            contents.hash(&mut hasher);
         }
     }
+    format!("{:08x}", hasher.finish() as u32)
 }
 
-async fn get_entry(
-    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
-    Path(rowid): Path<u32>,
-) -> Response {
-    use ammonia::clean;
-    use pulldown_cmark::{html::push_html, Options, Parser};
+const DEFAULT_SLOW_REQUEST_MS: u64 = 500;
 
-    let mut cxn = lock_db(&cxn_arcmux)?;
-    let mut entry: EntryViewModel = Entry::try_fetch(&mut cxn, rowid)?.into();
+const DEFAULT_REMINDER_HOURS: u32 = 24;
 
-    let mut unsafe_html = String::new();
-    {
-        let mut options = Options::empty();
-        options.insert(Options::ENABLE_SMART_PUNCTUATION);
-        let md_parse = Parser::new_ext(&entry.body, options);
-        push_html(&mut unsafe_html, md_parse);
-    }
-    let safe_html = clean(&unsafe_html);
-    entry.body = safe_html;
+/// How often `remind_to_write` looks for the newest entry.
+const REMINDER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
 
-    let body = entry.render().map_err(|e| {
-        error!("{:?}", e);
-        (StatusCode::INTERNAL_SERVER_ERROR, "".to_owned())
-    })?;
-    Ok(Html(body))
-}
+/// Call `webhook` when the diary at `dbpath` has gone `hours` without a new entry. Each
+/// reminder is sent once; the next one waits for another entry and another quiet spell.
+async fn remind_to_write(name: String, dbpath: String, webhook: String, hours: u32) {
+    use chrono::TimeZone;
 
-fn year_counts(cxn: &mut rusqlite::Connection) -> Result<Vec<(u32, u32)>, AppError> {
-    let qry = r#"
-        SELECT
-            strftime('%Y', date) AS year,
-            COUNT(*) as cnt
-        FROM entries
-        GROUP BY year
-        ORDER BY year DESC
-    "#;
-    let mut stmt = cxn.prepare(qry).map_err(convert_db_error)?;
-    let rows = stmt
-        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
-        .map_err(convert_db_error)?;
-    let mut results = Vec::new();
-    for row in rows {
-        let raw: (String, u32) = row.map_err(convert_db_error)?;
-        let year: u32 = raw.0.parse().map_err(|e| {
-            error!("{:?}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Year parsing error".to_string(),
-            )
-        })?;
-        results.push((year, raw.1));
+    let agent = webhook_agent();
+    let mut interval = tokio::time::interval(REMINDER_CHECK_INTERVAL);
+    // The newest entry's timestamp (if any) when the last reminder was sent.
+    let mut reminded_for: Option<Option<i64>> = None;
+    loop {
+        interval.tick().await;
+        let latest = match latest_entry_timestamp(&dbpath) {
+            Ok(latest) => latest,
+            Err(e) => {
+                warn!("Couldn't check {} for a writing reminder: {}", dbpath, e);
+                continue;
+            }
+        };
+        let overdue = latest.is_none_or(|timestamp| {
+            Utc::now().timestamp() - timestamp >= i64::from(hours) * 60 * 60
+        });
+        if !overdue || reminded_for == Some(latest) {
+            continue;
+        }
+        let payload = serde_json::json!({
+            "diary": name,
+            "last_entry": latest.and_then(|ts| Utc.timestamp_opt(ts, 0).single()),
+            "message": format!("No diary entry in the last {} hours", hours),
+        });
+        if post_webhook(&agent, &webhook, payload).await {
+            info!("Sent a writing reminder for {}", name);
+            reminded_for = Some(latest);
+        }
     }
-    Ok(results)
 }
 
-#[derive(Template)]
-#[template(path = "year.html")]
-struct YearViewModel {
-    year: u32,
-    months: Vec<(chrono::Month, Vec<Entry>)>,
-    entry_count: u32,
+fn webhook_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout(std::time::Duration::from_secs(30))
+        .build()
 }
 
-impl Entry {
-    fn month(&self) -> Result<chrono::Month, AppError> {
-        use chrono::prelude::*;
-        use num_traits::FromPrimitive;
-
-        Month::from_u32(self.timestamp.month()).ok_or((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Date conversion error".to_string(),
-        ))
+/// POST `payload` to `webhook` without blocking the runtime, logging failures. Returns
+/// whether it was delivered.
+async fn post_webhook(agent: &ureq::Agent, webhook: &str, payload: serde_json::Value) -> bool {
+    let request = agent.post(webhook).set("Content-Type", "application/json");
+    let sent = tokio::task::spawn_blocking(move || {
+        request
+            .send_string(&payload.to_string())
+            .map_err(|e| e.to_string())
+    })
+    .await;
+    match sent {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            warn!("Webhook {} failed: {}", webhook, e);
+            false
+        }
+        Err(e) => {
+            error!("Webhook task panicked: {}", e);
+            false
+        }
     }
 }
 
-impl YearViewModel {
-    fn get(cxn: &mut rusqlite::Connection, year: u32) -> Result<Self, AppError> {
-        use chrono::Month;
-        const QUERY: &str = r#"
-        SELECT rowid, date, timestamp, body,
-            strftime('%Y', date) as year, strftime('%m', date) as month
-        FROM entries
-        WHERE ? = CAST(year AS INTEGER)
-        ORDER BY month
-        "#;
-        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
-        let mut entries: HashMap<chrono::Month, Vec<Entry>> = HashMap::new();
-        let results = qry
-            .query_map([year], RawEntry::from_row)
-            .map_err(convert_db_error)?;
-        let mut entry_count = 0;
-        for raw in results {
-            let raw = raw.map_err(convert_db_error)?;
-            let entry: Entry = raw.try_into()?;
-            let month = entry.month()?;
-            if let Some(month_list) = entries.get_mut(&month) {
-                month_list.push(entry);
-            } else {
-                entries.insert(month, vec![entry]);
+/// Streak lengths, in days, worth a notification.
+const STREAK_MILESTONES: [u32; 4] = [7, 30, 100, 365];
+
+/// Call `webhook` when the diary's streak of consecutive days with entries reaches a
+/// milestone or is broken. The streak seen at startup is taken as the baseline, so
+/// restarting the server doesn't repeat notifications.
+async fn watch_streak(name: String, dbpath: String, webhook: String) {
+    let agent = webhook_agent();
+    let mut interval = tokio::time::interval(REMINDER_CHECK_INTERVAL);
+    let mut previous: Option<u32> = None;
+    loop {
+        interval.tick().await;
+        let today = chrono::Local::now().date_naive();
+        let streak = match current_streak(&dbpath, today) {
+            Ok(streak) => streak,
+            Err(e) => {
+                warn!("Couldn't check {} for a streak: {}", dbpath, e);
+                continue;
             }
-            entry_count += 1;
+        };
+        let Some(before) = previous else {
+            previous = Some(streak);
+            continue;
+        };
+        let payload = if let Some(&milestone) = STREAK_MILESTONES
+            .iter()
+            .rev()
+            .find(|&&m| before < m && m <= streak)
+        {
+            serde_json::json!({
+                "diary": name,
+                "event": "streak_milestone",
+                "streak": milestone,
+                "message": format!("{} days in a row of diary entries!", milestone),
+            })
+        } else if streak == 0 && before > 1 {
+            serde_json::json!({
+                "diary": name,
+                "event": "streak_broken",
+                "streak": before,
+                "message": format!("Your {}-day diary streak has ended", before),
+            })
+        } else {
+            previous = Some(streak);
+            continue;
+        };
+        // Keep the old streak if the webhook fails, so the next check tries again.
+        if post_webhook(&agent, &webhook, payload).await {
+            info!("Sent a streak notification for {}", name);
+            previous = Some(streak);
         }
-        let mut months: Vec<(Month, Vec<Entry>)> = entries.into_iter().collect();
-        months.sort_by(|(a, _), (b, _)| a.number_from_month().cmp(&b.number_from_month()));
-        for (_, month) in months.iter_mut() {
-            month.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    }
+}
+
+/// How many consecutive days up to `today` have entries. A streak that reached yesterday is
+/// still going, since there's time left to write today.
+fn current_streak(dbpath: &str, today: NaiveDate) -> rusqlite::Result<u32> {
+    use rusqlite::OpenFlags;
+
+    let cxn = Connection::open_with_flags(dbpath, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = cxn.prepare("SELECT DISTINCT date FROM entries ORDER BY date DESC")?;
+    let dates = stmt.query_map([], |r| r.get::<_, NaiveDate>(0))?;
+    let mut streak = 0;
+    let mut expected = today;
+    for date in dates {
+        let date = date?;
+        if date > today {
+            continue;
         }
-        Ok(YearViewModel {
-            year,
-            months,
-            entry_count,
-        })
+        if streak == 0 && date < today {
+            expected = today.pred_opt().unwrap_or(today);
+        }
+        if date != expected {
+            break;
+        }
+        streak += 1;
+        expected = match date.pred_opt() {
+            Some(day) => day,
+            None => break,
+        };
     }
+    Ok(streak)
 }
 
-async fn get_year(
-    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
-    Path(year): Path<u32>,
-) -> Response {
-    let mut cxn = lock_db(&cxn_arcmux)?;
-    let vm = YearViewModel::get(&mut cxn, year)?;
-    let body = vm.render().map_err(convert_render_error)?;
-    Ok(Html(body))
+/// When the newest entry was written, read with a connection of its own so reminders don't
+/// wait on requests.
+fn latest_entry_timestamp(dbpath: &str) -> rusqlite::Result<Option<i64>> {
+    use rusqlite::OpenFlags;
+
+    let cxn = Connection::open_with_flags(dbpath, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    cxn.query_row("SELECT MAX(timestamp) FROM entries", [], |r| r.get(0))
 }
 
-#[derive(Template)]
-#[template(path = "search.html")]
-struct SearchViewModel {
-    query: String,
-    results: Vec<SearchResult>,
+/// Warn about requests that take longer than `threshold`, which `TraceLayer` logs like any
+/// other.
+async fn log_slow_request<B>(
+    threshold: std::time::Duration,
+    req: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+    let start = std::time::Instant::now();
+    let response = next.run(req).await;
+    let elapsed = start.elapsed();
+    if elapsed > threshold {
+        warn!(
+            "Slow request: {} {} -> {} in {}ms",
+            method,
+            uri,
+            response.status(),
+            elapsed.as_millis()
+        );
+    }
+    response
 }
 
-struct SearchResult {
-    entry_id: u32,
-    entry_timestamp: DateTime<Utc>,
-    entry_match: String,
+/// How many server errors `/admin/errors` remembers.
+const RECENT_ERRORS_LIMIT: usize = 50;
+
+/// Longest error message kept, in characters.
+const RECENT_ERROR_LENGTH: usize = 1000;
+
+#[derive(Clone)]
+struct RecentError {
+    at: DateTime<Utc>,
+    status: StatusCode,
+    method: String,
+    path: String,
+    message: String,
 }
 
-impl TryFrom<RawSearchResult> for SearchResult {
-    type Error = AppError;
+/// The latest server errors, oldest first, so they can be seen without reading the logs.
+/// Kept in memory only, and lost on restart.
+#[derive(Clone, Default)]
+struct RecentErrors(Arc<Mutex<std::collections::VecDeque<RecentError>>>);
 
-    fn try_from(raw: RawSearchResult) -> Result<Self, Self::Error> {
-        use chrono::NaiveDateTime;
-        let RawSearchResult {
-            entry_id,
-            entry_timestamp,
-            entry_match,
-        } = raw;
-        let ndt = NaiveDateTime::from_timestamp_opt(entry_timestamp as i64, 0).ok_or((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            "Timestamp conversion errror".to_owned(),
-        ))?;
-        let entry_timestamp = DateTime::from_utc(ndt, Utc);
-        let result = SearchResult {
-            entry_id,
-            entry_timestamp,
-            entry_match,
-        };
-        Ok(result)
+impl RecentErrors {
+    fn record(&self, error: RecentError) {
+        if let Ok(mut errors) = self.0.lock() {
+            if errors.len() == RECENT_ERRORS_LIMIT {
+                errors.pop_front();
+            }
+            errors.push_back(error);
+        }
     }
 }
 
-struct RawSearchResult {
-    entry_id: u32,
-    entry_timestamp: u32,
-    entry_match: String,
+/// Remember 5xx responses along with the error message in their body.
+async fn record_server_error<B>(
+    errors: RecentErrors,
+    req: axum::http::Request<B>,
+    next: axum::middleware::Next<B>,
+) -> axum::response::Response {
+    use axum::body::{boxed, Full};
+    use chrono::SubsecRound;
+
+    let method = req.method().to_string();
+    let path = req.uri().path().to_owned();
+    let response = next.run(req).await;
+    if !response.status().is_server_error() {
+        return response;
+    }
+    let (parts, body) = response.into_parts();
+    let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+    errors.record(RecentError {
+        at: Utc::now().trunc_subsecs(0),
+        status: parts.status,
+        method,
+        path,
+        message: String::from_utf8_lossy(&body)
+            .chars()
+            .take(RECENT_ERROR_LENGTH)
+            .collect(),
+    });
+    axum::response::Response::from_parts(parts, boxed(Full::from(body)))
 }
 
-impl TryFrom<&rusqlite::Row<'_>> for RawSearchResult {
-    type Error = rusqlite::Error;
+/// How many admin actions the log keeps.
+const ADMIN_LOG_LIMIT: u32 = 500;
 
-    fn try_from(row: &rusqlite::Row) -> Result<Self, Self::Error> {
-        let entry_id = row.get(0)?;
-        let entry_timestamp = row.get(1)?;
-        let entry_match = row.get(2)?;
+/// Note a maintenance action (a reindex, import, migration, or lock) in the diary's admin
+/// log, forgetting the oldest beyond `ADMIN_LOG_LIMIT`.
+fn record_admin_action(cxn: &Connection, action: &str, summary: &str) -> rusqlite::Result<()> {
+    const RECORD: &str = r#"
+        INSERT INTO admin_log (action, summary, created_at) VALUES ($1, $2, unixepoch('now'))
+    "#;
+    const TRIM: &str = r#"
+        DELETE FROM admin_log
+        WHERE rowid NOT IN (SELECT rowid FROM admin_log ORDER BY rowid DESC LIMIT $1)
+    "#;
+    cxn.execute(RECORD, [action, summary])?;
+    cxn.execute(TRIM, [ADMIN_LOG_LIMIT])?;
+    Ok(())
+}
 
-        let result = RawSearchResult {
-            entry_id,
-            entry_timestamp,
-            entry_match,
-        };
-        Ok(result)
+#[derive(serde::Serialize)]
+struct AdminAction {
+    action: String,
+    summary: String,
+    at: DateTime<Utc>,
+}
+
+/// The admin log, newest first.
+fn admin_log(cxn: &Connection) -> Result<Vec<AdminAction>, AppError> {
+    const QUERY: &str = r#"
+        SELECT action, summary, created_at FROM admin_log ORDER BY rowid DESC
+    "#;
+    let mut stmt = cxn.prepare(QUERY).map_err(convert_db_error)?;
+    let rows = stmt
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .map_err(convert_db_error)?;
+    let mut actions = Vec::new();
+    for row in rows {
+        let (action, summary, created_at) = row.map_err(convert_db_error)?;
+        actions.push(AdminAction {
+            action,
+            summary,
+            at: convert_timestamp(created_at)?,
+        });
     }
+    Ok(actions)
 }
 
-async fn get_search(
+async fn get_admin_log(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+) -> Result<Json<Vec<AdminAction>>, ApiError> {
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    Ok(Json(admin_log(&cxn)?))
+}
+
+#[derive(Template)]
+#[template(path = "admin-log.html")]
+struct AdminLogViewModel {
+    site: Site,
+    actions: Vec<AdminAction>,
+}
+
+async fn get_admin_log_page(
     Extension(cxn_arcmux): Extension<ConnectionArcMux>,
-    Query(query_args): Query<HashMap<String, String>>,
+    site: Site,
 ) -> Response {
-    let cxn = lock_db(&cxn_arcmux)?;
-    const QUERY: &str = r#"
-        SELECT entries.rowid, entries.timestamp, snippet(entrytext, 0, '', '', '...', 32)
-        FROM entrytext
-        JOIN entries ON entrytext.rowid = entries.rowid
-        WHERE entrytext MATCH ?
-        ORDER BY timestamp DESC
-    "#;
-    let qry = query_args.get("q");
-    info!("Search for: {:?}", qry);
-    let results: Vec<SearchResult> = if let Some(qry) = qry {
-        let mut stmt = cxn.prepare(QUERY).map_err(convert_db_error)?;
-        let raw_results = stmt
-            .query_map([qry], |r| r.try_into())
-            .map_err(convert_db_error)?;
-        let mut results = Vec::new();
-        for raw in raw_results {
-            let result: RawSearchResult = raw.map_err(convert_db_error)?;
-            results.push(result.try_into()?);
-        }
-        results
-    } else {
-        Vec::new()
-    };
-    dbg!("Found {} results", results.len());
-    let vm = SearchViewModel {
-        results,
-        query: qry.cloned().unwrap_or_default(),
-    };
-    let body = vm.render().map_err(convert_render_error)?;
-    Ok(Html(body))
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let actions = admin_log(&cxn)?;
+    render(AdminLogViewModel { site, actions })
 }
 
 #[derive(serde::Deserialize)]
-struct Draft {
-    body: String,
+struct DeleteRangeQuery {
+    from: Option<String>,
+    to: Option<String>,
 }
 
-async fn post_draft(
-    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
-    Form(draft): Form<Draft>,
-) -> Result<String, AppError> {
-    let mut cxn = lock_db(&cxn_arcmux)?;
-    const CREATE: &str = r#"
-        INSERT INTO draft (draft) VALUES ($1)
-    "#;
-    clear_draft(&mut cxn)?;
-    cxn.execute(CREATE, [&draft.body])
-        .map_err(convert_db_error)?;
-    Ok(String::from("Saved"))
+#[derive(serde::Deserialize)]
+struct DeleteRange {
+    from: String,
+    to: String,
+    /// How many entries the confirmation page said would be deleted.
+    count: u32,
 }
 
-fn clear_draft(cxn: &mut Connection) -> Result<(), AppError> {
-    const TRUNCATE: &str = r#"
-        DELETE FROM draft
-    "#;
-    cxn.execute(TRUNCATE, []).map_err(convert_db_error)?;
-    Ok(())
+/// First and last day of a range, inclusive.
+fn parse_date_range(from: &str, to: &str) -> Result<(NaiveDate, NaiveDate), AppError> {
+    let (from, to) = (parse_date(from)?, parse_date(to)?);
+    if from > to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("The range ends ({}) before it starts ({})", to, from),
+        ));
+    }
+    Ok((from, to))
 }
 
-fn get_draft(cxn: &mut Connection) -> Result<Option<String>, AppError> {
-    const GET: &str = r#"
-        SELECT draft FROM draft LIMIT 1
+#[derive(Template)]
+#[template(path = "delete-range.html")]
+struct DeleteRangeViewModel {
+    site: Site,
+    /// The range to confirm deleting, once one has been chosen.
+    range: Option<(NaiveDate, NaiveDate)>,
+    /// Entries in the range.
+    count: u32,
+    /// Locked entries in the range, which stop it being deleted.
+    locked: u32,
+    /// How many entries were deleted, after confirming.
+    deleted: Option<usize>,
+}
+
+/// How many entries fall in a range, and how many of those are locked.
+fn count_range(cxn: &Connection, from: NaiveDate, to: NaiveDate) -> Result<(u32, u32), AppError> {
+    const QUERY: &str = r#"
+        SELECT COUNT(*), COALESCE(SUM(locked), 0) FROM entries WHERE date BETWEEN $1 AND $2
     "#;
-    cxn.query_row(GET, [], |r| r.get(0))
-        .optional()
-        .map_err(convert_db_error)
+    cxn.query_row(QUERY, [from.to_string(), to.to_string()], |r| {
+        Ok((r.get(0)?, r.get(1)?))
+    })
+    .map_err(convert_db_error)
+}
+
+/// Pick a range of days to delete, then confirm how many entries that would delete.
+async fn get_delete_range(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageQuery(query): PageQuery<DeleteRangeQuery>,
+) -> Response {
+    let range = match (query.from, query.to) {
+        (Some(from), Some(to)) => Some(parse_date_range(&from, &to)?),
+        _ => None,
+    };
+    let (count, locked) = match range {
+        Some((from, to)) => count_range(&*lock_db(&cxn_arcmux, &site).await?, from, to)?,
+        None => (0, 0),
+    };
+    render(DeleteRangeViewModel {
+        site,
+        range,
+        count,
+        locked,
+        deleted: None,
+    })
+}
+
+/// Refuse requests sent by another site's pages, going by `Sec-Fetch-Site` or, in browsers
+/// that don't send it, `Origin`. Requests with neither (like `curl`'s) are allowed.
+fn check_same_site(headers: &axum::http::HeaderMap) -> Result<(), AppError> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let same_site = match (header("sec-fetch-site"), header("origin")) {
+        (Some(site), _) => site == "same-origin" || site == "none",
+        (None, Some(origin)) => {
+            let origin_host = origin.split_once("://").map(|(_, host)| host);
+            origin_host.is_some() && origin_host == header("host")
+        }
+        (None, None) => true,
+    };
+    if same_site {
+        Ok(())
+    } else {
+        Err((
+            StatusCode::FORBIDDEN,
+            String::from("This form can only be sent from the diary's own pages."),
+        ))
+    }
+}
+
+/// Permanently delete every entry written on the days from `from` to `to`, along with
+/// their history and revisions, leaving a tombstone for `/api/changes` to report. Refused
+/// if any of them are locked, or if the range no longer holds the number of entries the
+/// user confirmed deleting.
+async fn post_delete_range(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    headers: axum::http::HeaderMap,
+    PageForm(range): PageForm<DeleteRange>,
+) -> Response {
+    const ENTRIES: &str = "SELECT rowid FROM entries WHERE date BETWEEN $1 AND $2";
+    check_same_site(&headers)?;
+    site.check_writable()?;
+    let (from, to) = parse_date_range(&range.from, &range.to)?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    let (count, locked) = count_range(&tx, from, to)?;
+    if count != range.count {
+        return Err((
+            StatusCode::CONFLICT,
+            format!(
+                "There are now {} entries from {} to {}, not the {} you confirmed; count them again before deleting.",
+                count, from, to, range.count
+            ),
+        ));
+    }
+    if locked > 0 {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!(
+                "{} entries from {} to {} are locked; unlock them before deleting.",
+                locked, from, to
+            ),
+        ));
+    }
+    let params = [from.to_string(), to.to_string()];
+    let related = [
+        ("entrytext", "rowid"),
+        ("history", "entry_id"),
+        ("revisions", "entry_id"),
+        ("idempotency_keys", "entry_id"),
+    ];
+    for (table, column) in related {
+        let delete = format!("DELETE FROM {} WHERE {} IN ({})", table, column, ENTRIES);
+        tx.execute(&delete, params.clone())
+            .map_err(convert_db_error)?;
+    }
+    const TOMBSTONES: &str = r#"
+        INSERT INTO deleted_entries (entry_id, date, deleted_at)
+        SELECT rowid, date, unixepoch('now') FROM entries WHERE date BETWEEN $1 AND $2
+    "#;
+    tx.execute(TOMBSTONES, params.clone())
+        .map_err(convert_db_error)?;
+    let deleted = tx
+        .execute("DELETE FROM entries WHERE date BETWEEN $1 AND $2", params)
+        .map_err(convert_db_error)?;
+    record_admin_action(
+        &tx,
+        "delete range",
+        &format!("Deleted {} entries from {} to {}", deleted, from, to),
+    )
+    .map_err(convert_db_error)?;
+    tx.commit().map_err(convert_db_error)?;
+    site.invalidate_stats();
+    render(DeleteRangeViewModel {
+        site,
+        range: Some((from, to)),
+        count: 0,
+        locked: 0,
+        deleted: Some(deleted),
+    })
+}
+
+#[derive(Template)]
+#[template(path = "errors.html")]
+struct RecentErrorsViewModel {
+    site: Site,
+    errors: Vec<RecentError>,
+}
+
+/// Recent server errors, newest first.
+async fn get_recent_errors(site: Site, Extension(errors): Extension<RecentErrors>) -> Response {
+    let errors = match errors.0.lock() {
+        Ok(errors) => errors.iter().rev().cloned().collect(),
+        Err(e) => {
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Couldn't lock the error log: {:?}", e),
+            ))
+        }
+    };
+    render(RecentErrorsViewModel { site, errors })
+}
+
+fn diary_routes(cxn: rusqlite::Connection, site: SiteConfig) -> axum::Router {
+    use axum::middleware::map_response;
+    use axum::routing::{get, post, Router};
+
+    let cxn_arcmut = Arc::new(tokio::sync::Mutex::new(cxn));
+
+    Router::new()
+        .route("/", get(get_index))
+        .route("/new", get(get_new_entry).post(post_new_entry))
+        .route("/draft", get(get_draft_status).post(post_draft))
+        .route("/draft/clear", post(post_clear_draft))
+        .route("/entry/:rowid", get(get_entry))
+        .route("/entry/:rowid/clone", get(get_clone_entry))
+        .route("/entry/:rowid/plain", get(get_entry_plain))
+        .route("/entry/:rowid/raw", get(get_entry_raw))
+        .route("/entry/:rowid/print", get(get_entry_print))
+        .route("/entry/:rowid/history", get(get_entry_history))
+        .route("/entry/:rowid/diff/:rev", get(get_entry_diff))
+        .route("/entry/:rowid/lock", post(post_toggle_lock))
+        .route("/entry/:rowid/toggle-task", post(post_toggle_task))
+        .route(
+            "/entry/:rowid/edit",
+            get(get_edit_entry).post(post_edit_entry),
+        )
+        .route("/latest", get(get_latest))
+        .route("/first", get(get_first))
+        .route("/year/:year", get(get_year))
+        .route("/year/:year/export.zip", get(get_year_export))
+        .route("/year/:year/:month", get(get_month))
+        .route("/day", get(get_day_query))
+        .route("/day/:date", get(get_day))
+        .route("/search", get(get_search).post(post_search))
+        .route("/search/export.md", get(get_search_export))
+        .route("/mood/:mood", get(get_mood))
+        .route("/undo", post(post_undo))
+        .route("/admin/log", get(get_admin_log))
+        .route("/admin/log.html", get(get_admin_log_page))
+        .route(
+            "/admin/delete-range",
+            get(get_delete_range).post(post_delete_range),
+        )
+        .route("/api/changes", get(get_changes))
+        .route("/api/entry", post(post_api_entry))
+        .route("/api/search", get(get_api_search))
+        .route("/api/suggest", get(get_api_suggest))
+        .route("/api/count", get(get_api_count))
+        .route("/api/activity-by-hour", get(get_activity_by_hour))
+        .route("/api/years", get(get_api_years))
+        .route("/api/year/:year", get(get_api_year))
+        // Keep `get_openapi` in step with the API routes above.
+        .route("/api/openapi.json", get(get_openapi))
+        .layer(map_response(render_method_not_allowed))
+        .layer(Extension(cxn_arcmut))
+        .layer(Extension(Arc::new(site)))
+}
+
+/// Settings shared by every page of a diary.
+struct SiteConfig {
+    /// Path that the diary's routes are nested under, including the base path; empty when
+    /// it's served from the root.
+    prefix: String,
+    /// Path that the whole app is served under, e.g. for static files.
+    base_path: String,
+    /// Added to static asset URLs so browsers fetch them again after a deploy.
+    asset_version: String,
+    /// Cached for the footer; cleared whenever entries are written.
+    stats: Mutex<Option<DiaryStats>>,
+    title: String,
+    author: Option<String>,
+    markdown: MarkdownOptions,
+    /// Set during maintenance, or when the database file can't be written to: pages can be
+    /// read but nothing can be written.
+    read_only: bool,
+    /// How recently an identical entry must have been saved for a new one to be treated as
+    /// a double submission.
+    duplicate_window_secs: u32,
+    /// How long an API client can retry a request with the same `Idempotency-Key`.
+    idempotency_window_secs: u32,
+    /// How many entries each month on the year page lists.
+    entries_per_month: usize,
+    /// How many characters of each recent entry the index shows.
+    index_preview_length: usize,
+    /// Journaling prompts for the new entry page; empty when none are configured.
+    prompts: Vec<String>,
+    /// Longest search query, in characters, that will be passed to FTS.
+    max_query_length: usize,
+    /// How many matches a search counts and pages through before giving up.
+    max_search_results: u32,
+    snippet: SnippetOptions,
+    locale: Locale,
+    /// The day "this week" starts on.
+    week_start: chrono::Weekday,
+    /// How long `lock_db` waits for a busy connection.
+    db_lock_timeout: std::time::Duration,
+}
+
+const DEFAULT_SITE_TITLE: &str = "Diary";
+const DEFAULT_DUPLICATE_WINDOW_SECS: u32 = 10;
+const DEFAULT_IDEMPOTENCY_WINDOW_SECS: u32 = 24 * 60 * 60;
+const DEFAULT_ENTRIES_PER_MONTH: usize = 10;
+const DEFAULT_INDEX_PREVIEW_LENGTH: usize = 300;
+const DEFAULT_MAX_QUERY_LENGTH: usize = 256;
+const DEFAULT_MAX_SEARCH_RESULTS: u32 = 10_000;
+
+/// Settings from the command line that every diary shares.
+#[derive(Clone)]
+struct SiteOptions {
+    title: String,
+    author: Option<String>,
+    markdown: MarkdownOptions,
+    read_only: bool,
+    duplicate_window_secs: u32,
+    idempotency_window_secs: u32,
+    entries_per_month: usize,
+    index_preview_length: usize,
+    prompts: Vec<String>,
+    base_path: String,
+    asset_version: String,
+    max_query_length: usize,
+    max_search_results: u32,
+    snippet: SnippetOptions,
+    locale: Locale,
+    week_start: chrono::Weekday,
+    db_lock_timeout: std::time::Duration,
+}
+
+impl Default for SiteOptions {
+    fn default() -> Self {
+        SiteOptions {
+            title: String::from(DEFAULT_SITE_TITLE),
+            author: None,
+            markdown: MarkdownOptions::default(),
+            read_only: false,
+            duplicate_window_secs: DEFAULT_DUPLICATE_WINDOW_SECS,
+            idempotency_window_secs: DEFAULT_IDEMPOTENCY_WINDOW_SECS,
+            entries_per_month: DEFAULT_ENTRIES_PER_MONTH,
+            index_preview_length: DEFAULT_INDEX_PREVIEW_LENGTH,
+            prompts: Vec::new(),
+            base_path: String::new(),
+            asset_version: String::new(),
+            max_query_length: DEFAULT_MAX_QUERY_LENGTH,
+            max_search_results: DEFAULT_MAX_SEARCH_RESULTS,
+            snippet: SnippetOptions::default(),
+            locale: Locale::default(),
+            week_start: chrono::Weekday::Mon,
+            db_lock_timeout: std::time::Duration::from_millis(DEFAULT_DB_LOCK_TIMEOUT_MS),
+        }
+    }
+}
+
+impl SiteConfig {
+    fn new(prefix: String, options: SiteOptions) -> Self {
+        SiteConfig {
+            prefix: format!("{}{}", options.base_path, prefix),
+            base_path: options.base_path,
+            asset_version: options.asset_version,
+            stats: Mutex::new(None),
+            title: options.title,
+            author: options.author,
+            markdown: options.markdown,
+            read_only: options.read_only,
+            duplicate_window_secs: options.duplicate_window_secs,
+            idempotency_window_secs: options.idempotency_window_secs,
+            entries_per_month: options.entries_per_month,
+            index_preview_length: options.index_preview_length,
+            prompts: options.prompts,
+            max_query_length: options.max_query_length,
+            max_search_results: options.max_search_results,
+            snippet: options.snippet,
+            locale: options.locale,
+            week_start: options.week_start,
+            db_lock_timeout: options.db_lock_timeout,
+        }
+    }
+
+    fn month_name(&self, month: &chrono::Month) -> &'static str {
+        self.locale.month_name(*month)
+    }
+
+    /// The prompt for `day`, picked at random but the same all day.
+    fn prompt(&self, day: NaiveDate) -> Option<&str> {
+        use std::hash::{Hash, Hasher};
+
+        if self.prompts.is_empty() {
+            return None;
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        day.hash(&mut hasher);
+        let i = hasher.finish() % self.prompts.len() as u64;
+        Some(&self.prompts[i as usize])
+    }
+
+    fn root() -> Self {
+        SiteConfig::new(String::new(), SiteOptions::default())
+    }
+
+    /// Called by every handler that writes to the database.
+    fn check_writable(&self) -> Result<(), AppError> {
+        if self.read_only {
+            return Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                String::from("The diary is read-only at the moment; changes can't be saved."),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Refuse queries long enough to make FTS slow, before running them.
+    fn check_query_length(&self, query: &str) -> Result<(), AppError> {
+        if query.chars().count() > self.max_query_length {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "Searches can be at most {} characters long; try a shorter one.",
+                    self.max_query_length
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path)
+    }
+
+    /// The diary's stats, from the cache if they haven't changed since they were computed.
+    async fn stats(&self, cxn_arcmux: &ConnectionArcMux) -> Option<DiaryStats> {
+        if let Some(stats) = *self.stats.lock().ok()? {
+            return Some(stats);
+        }
+        let stats = lock_db(cxn_arcmux, self)
+            .await
+            .and_then(|cxn| DiaryStats::fetch(&cxn))
+            .ok()?;
+        *self.stats.lock().ok()? = Some(stats);
+        Some(stats)
+    }
+
+    fn invalidate_stats(&self) {
+        if let Ok(mut stats) = self.stats.lock() {
+            *stats = None;
+        }
+    }
+}
+
+/// The size of a diary, shown in the footer of every page.
+#[derive(Clone, Copy)]
+struct DiaryStats {
+    entries: u32,
+    bytes: u64,
+}
+
+impl DiaryStats {
+    fn fetch(cxn: &Connection) -> Result<Self, AppError> {
+        const QUERY: &str = r#"
+            SELECT
+                (SELECT COUNT(*) FROM entries),
+                page_count * page_size
+            FROM pragma_page_count(), pragma_page_size()
+        "#;
+        cxn.query_row(QUERY, [], |r| {
+            Ok(DiaryStats {
+                entries: r.get(0)?,
+                bytes: r.get(1)?,
+            })
+        })
+        .map_err(convert_db_error)
+    }
+
+    fn size(&self) -> String {
+        const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+        let mut size = self.bytes as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        if unit == 0 {
+            format!("{} {}", self.bytes, UNITS[0])
+        } else {
+            format!("{:.1} {}", size, UNITS[unit])
+        }
+    }
+}
+
+/// The language dates are written in.
+#[derive(Clone, Copy, Default, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Locale {
+    #[default]
+    En,
+    De,
+    Es,
+    Fr,
+    It,
+    Nl,
+    Pt,
+}
+
+impl Locale {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "en" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            "es" => Some(Locale::Es),
+            "fr" => Some(Locale::Fr),
+            "it" => Some(Locale::It),
+            "nl" => Some(Locale::Nl),
+            "pt" => Some(Locale::Pt),
+            _ => None,
+        }
+    }
+
+    /// The language's BCP 47 tag, for `<html lang>`.
+    fn tag(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+            Locale::Es => "es",
+            Locale::Fr => "fr",
+            Locale::It => "it",
+            Locale::Nl => "nl",
+            Locale::Pt => "pt",
+        }
+    }
+
+    /// The month's name, capitalized to head a section.
+    fn month_name(self, month: chrono::Month) -> &'static str {
+        const DE: [&str; 12] = [
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+        ];
+        const ES: [&str; 12] = [
+            "Enero",
+            "Febrero",
+            "Marzo",
+            "Abril",
+            "Mayo",
+            "Junio",
+            "Julio",
+            "Agosto",
+            "Septiembre",
+            "Octubre",
+            "Noviembre",
+            "Diciembre",
+        ];
+        const FR: [&str; 12] = [
+            "Janvier",
+            "Février",
+            "Mars",
+            "Avril",
+            "Mai",
+            "Juin",
+            "Juillet",
+            "Août",
+            "Septembre",
+            "Octobre",
+            "Novembre",
+            "Décembre",
+        ];
+        const IT: [&str; 12] = [
+            "Gennaio",
+            "Febbraio",
+            "Marzo",
+            "Aprile",
+            "Maggio",
+            "Giugno",
+            "Luglio",
+            "Agosto",
+            "Settembre",
+            "Ottobre",
+            "Novembre",
+            "Dicembre",
+        ];
+        const NL: [&str; 12] = [
+            "Januari",
+            "Februari",
+            "Maart",
+            "April",
+            "Mei",
+            "Juni",
+            "Juli",
+            "Augustus",
+            "September",
+            "Oktober",
+            "November",
+            "December",
+        ];
+        const PT: [&str; 12] = [
+            "Janeiro",
+            "Fevereiro",
+            "Março",
+            "Abril",
+            "Maio",
+            "Junho",
+            "Julho",
+            "Agosto",
+            "Setembro",
+            "Outubro",
+            "Novembro",
+            "Dezembro",
+        ];
+        let index = month.number_from_month() as usize - 1;
+        match self {
+            Locale::En => month.name(),
+            Locale::De => DE[index],
+            Locale::Es => ES[index],
+            Locale::Fr => FR[index],
+            Locale::It => IT[index],
+            Locale::Nl => NL[index],
+            Locale::Pt => PT[index],
+        }
+    }
+}
+
+impl std::fmt::Display for Locale {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.tag())
+    }
+}
+
+/// Everything `base.html` needs to lay out a page: the diary's settings and the reader's
+/// preferences for this request.
+struct Site {
+    config: Arc<SiteConfig>,
+    theme: Theme,
+    stats: Option<DiaryStats>,
+}
+
+impl std::ops::Deref for Site {
+    type Target = SiteConfig;
+
+    fn deref(&self) -> &SiteConfig {
+        &self.config
+    }
+}
+
+#[axum::async_trait]
+impl<S: Send + Sync> axum::extract::FromRequestParts<S> for Site {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        _state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        let config = parts
+            .extensions
+            .get::<Arc<SiteConfig>>()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(SiteConfig::root()));
+        let theme = Theme::requested(&parts.uri)
+            .or_else(|| cookie(&parts.headers, THEME_COOKIE).and_then(Theme::parse))
+            .unwrap_or_default();
+        let stats = match parts.extensions.get::<ConnectionArcMux>() {
+            Some(cxn) => config.stats(cxn).await,
+            None => None,
+        };
+        Ok(Site {
+            config,
+            theme,
+            stats,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+const THEME_COOKIE: &str = "theme";
+
+impl Theme {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            _ => None,
+        }
+    }
+
+    fn toggled(self) -> Self {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::Light,
+        }
+    }
+
+    /// The theme chosen with a `?theme=` query parameter, if any.
+    fn requested(uri: &axum::http::Uri) -> Option<Self> {
+        let query = uri.query()?;
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(k, _)| k == "theme")
+            .and_then(|(_, v)| Theme::parse(&v))
+    }
+}
+
+impl std::fmt::Display for Theme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Theme::Light => write!(f, "light"),
+            Theme::Dark => write!(f, "dark"),
+        }
+    }
+}
+
+fn cookie<'a>(headers: &'a axum::http::HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(axum::http::header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(k, _)| *k == name)
+        .map(|(_, v)| v)
+}
+
+/// Persist a theme chosen with `?theme=` so later pages render with it.
+async fn remember_theme(
+    uri: axum::http::Uri,
+    mut response: axum::response::Response,
+) -> axum::response::Response {
+    use axum::http::{header::SET_COOKIE, HeaderValue};
+
+    if let Some(theme) = Theme::requested(&uri) {
+        let cookie = format!(
+            "{}={}; Path=/; Max-Age=31536000; SameSite=Lax",
+            THEME_COOKIE, theme
+        );
+        if let Ok(value) = HeaderValue::from_str(&cookie) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+    response
+}
+
+#[derive(Template)]
+#[template(path = "diaries.html")]
+struct DiariesViewModel {
+    site: Site,
+    names: Vec<String>,
+}
+
+async fn get_diaries(site: Site, names: Vec<String>) -> Response {
+    let vm = DiariesViewModel { site, names };
+    render(vm)
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorViewModel {
+    site: Site,
+    status: StatusCode,
+    message: String,
+}
+
+/// Give axum's bare 405 responses the app's error page. The router adds their `Allow`
+/// header after this runs.
+async fn render_method_not_allowed(
+    site: Site,
+    method: axum::http::Method,
+    response: axum::response::Response,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    if response.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return response;
+    }
+    let vm = ErrorViewModel {
+        site,
+        status: StatusCode::METHOD_NOT_ALLOWED,
+        message: format!("This page doesn't support {} requests.", method),
+    };
+    (StatusCode::METHOD_NOT_ALLOWED, render(vm)).into_response()
+}
+
+/// Why a form couldn't be read, as a 400 whatever axum's rejection was.
+fn form_rejection_error(rejection: axum::extract::rejection::FormRejection) -> AppError {
+    use axum::extract::rejection::FormRejection;
+
+    let message = match rejection {
+        FormRejection::InvalidFormContentType(_) => String::from(
+            "The form wasn't sent as application/x-www-form-urlencoded, so it couldn't be read.",
+        ),
+        rejection => format!("The form couldn't be read: {}", rejection.body_text()),
+    };
+    (StatusCode::BAD_REQUEST, message)
+}
+
+/// `Form`, but a submission that can't be read gets the app's error page.
+struct PageForm<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequest<S, axum::body::Body> for PageForm<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request(
+        req: axum::http::Request<axum::body::Body>,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        use axum::extract::FromRequestParts;
+        use axum::response::IntoResponse;
+
+        let (mut parts, body) = req.into_parts();
+        let site = match Site::from_request_parts(&mut parts, state).await {
+            Ok(site) => site,
+            Err(never) => match never {},
+        };
+        let req = axum::http::Request::from_parts(parts, body);
+        match Form::<T>::from_request(req, state).await {
+            Ok(Form(value)) => Ok(PageForm(value)),
+            Err(rejection) => {
+                let (status, message) = form_rejection_error(rejection);
+                let vm = ErrorViewModel {
+                    site,
+                    status,
+                    message,
+                };
+                Err((status, render(vm)).into_response())
+            }
+        }
+    }
+}
+
+/// `Query`, but a query string that can't be read gets the app's error page.
+struct PageQuery<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequestParts<S> for PageQuery<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = axum::response::Response;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        use axum::response::IntoResponse;
+
+        let (status, message) = match Query::<T>::from_request_parts(parts, state).await {
+            Ok(Query(value)) => return Ok(PageQuery(value)),
+            Err(rejection) => query_rejection_error(rejection),
+        };
+        let site = match Site::from_request_parts(parts, state).await {
+            Ok(site) => site,
+            Err(never) => match never {},
+        };
+        let vm = ErrorViewModel {
+            site,
+            status,
+            message,
+        };
+        Err((status, render(vm)).into_response())
+    }
+}
+
+/// `Form` for routes that answer in JSON, so unreadable submissions get an `ApiError`.
+struct ApiForm<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequest<S, axum::body::Body> for ApiForm<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(
+        req: axum::http::Request<axum::body::Body>,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Form::<T>::from_request(req, state)
+            .await
+            .map(|Form(value)| ApiForm(value))
+            .map_err(|rejection| ApiError(form_rejection_error(rejection)))
+    }
+}
+
+/// `Json` for API routes, so unreadable bodies get an `ApiError`.
+struct ApiJson<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequest<S, axum::body::Body> for ApiJson<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(
+        req: axum::http::Request<axum::body::Body>,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Json::<T>::from_request(req, state)
+            .await
+            .map(|Json(value)| ApiJson(value))
+            .map_err(|rejection| ApiError((rejection.status(), rejection.body_text())))
+    }
+}
+
+/// `Query` for routes that answer in JSON, so unreadable query strings get an `ApiError`.
+struct ApiQuery<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequestParts<S> for ApiQuery<T>
+where
+    T: serde::de::DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Query::<T>::from_request_parts(parts, state)
+            .await
+            .map(|Query(value)| ApiQuery(value))
+            .map_err(|rejection| ApiError(query_rejection_error(rejection)))
+    }
+}
+
+fn query_rejection_error(rejection: axum::extract::rejection::QueryRejection) -> AppError {
+    (
+        StatusCode::BAD_REQUEST,
+        format!(
+            "The query string couldn't be read: {}",
+            rejection.body_text()
+        ),
+    )
+}
+
+/// `Path` for API routes, so a malformed path parameter gets an `ApiError`.
+struct ApiPath<T>(T);
+
+#[axum::async_trait]
+impl<T, S> axum::extract::FromRequestParts<S> for ApiPath<T>
+where
+    T: serde::de::DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        parts: &mut axum::http::request::Parts,
+        state: &S,
+    ) -> Result<Self, Self::Rejection> {
+        Path::<T>::from_request_parts(parts, state)
+            .await
+            .map(|Path(value)| ApiPath(value))
+            .map_err(|rejection| ApiError((rejection.status(), rejection.body_text())))
+    }
+}
+
+/// Render a page template into an HTML response.
+///
+/// Every page goes through here so they're all served as `text/html; charset=utf-8`;
+/// diary entries are frequently non-ASCII.
+fn render<T: Template>(vm: T) -> Response {
+    vm.render().map_err(convert_render_error).map(Html::from)
+}
+
+pub(crate) type AppError = (StatusCode, String);
+
+type Response = Result<Html<String>, AppError>;
+
+/// An `AppError` from a JSON route, reported as `{ "error": "...", "status": 404 }` so
+/// machine clients don't have to handle plain-text errors.
+struct ApiError(AppError);
+
+impl From<AppError> for ApiError {
+    fn from(err: AppError) -> Self {
+        ApiError(err)
+    }
+}
+
+impl axum::response::IntoResponse for ApiError {
+    fn into_response(self) -> axum::response::Response {
+        let (status, error) = self.0;
+        let body = serde_json::json!({
+            "error": error,
+            "status": status.as_u16(),
+        });
+        (status, Json(body)).into_response()
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Entry {
+    id: u32,
+    date: NaiveDate,
+    timestamp: DateTime<Utc>,
+    body: String,
+    mood: Option<String>,
+    /// When the entry was last written, as a Unix timestamp. Edits check it hasn't changed
+    /// since the entry was loaded so they can't silently overwrite each other.
+    updated_at: i64,
+}
+
+/// The moods an entry can be tagged with, from best to worst.
+const MOODS: &[&str] = &["great", "good", "okay", "low", "awful"];
+
+impl Entry {
+    fn try_fetch(cxn: &mut rusqlite::Connection, id: u32) -> Result<Self, AppError> {
+        const QUERY: &str = r#"
+            SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+            FROM entries
+            WHERE rowid = ?
+        "#;
+        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let entry = qry
+            .query_row([&id], RawEntry::from_row)
+            .map_err(convert_db_error)?
+            .try_into()?;
+        Ok(entry)
+    }
+}
+
+struct RawEntry {
+    id: u32,
+    date: String,
+    timestamp: u64,
+    body: String,
+    mood: Option<String>,
+    updated_at: i64,
+}
+
+impl RawEntry {
+    fn from_row(r: &rusqlite::Row) -> rusqlite::Result<Self> {
+        let timestamp = r.get(2)?;
+        let entry = RawEntry {
+            id: r.get(0)?,
+            date: r.get(1)?,
+            timestamp,
+            body: r.get(3)?,
+            mood: r.get(4)?,
+            // Only NULL in rows that were changed outside the app since they were migrated.
+            updated_at: r.get::<_, Option<i64>>(5)?.unwrap_or(timestamp as i64),
+        };
+
+        Ok(entry)
+    }
+}
+
+impl TryInto<Entry> for RawEntry {
+    type Error = AppError;
+    fn try_into(self) -> Result<Entry, Self::Error> {
+        let timestamp = convert_timestamp(self.timestamp as i64)?;
+
+        let entry = Entry {
+            id: self.id,
+            date: NaiveDate::parse_from_str(&self.date, "%Y-%m-%d").map_err(convert_parse_error)?,
+            timestamp,
+            body: self.body,
+            mood: self.mood,
+            updated_at: self.updated_at,
+        };
+        Ok(entry)
+    }
+}
+
+fn convert_db_error(err: rusqlite::Error) -> AppError {
+    use rusqlite::Error;
+    error!("{:?}", err);
+    match err {
+        Error::QueryReturnedNoRows => (StatusCode::NOT_FOUND, "Not found".to_owned()),
+        // The file was made read-only after the server started.
+        Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::ReadOnly => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The database is read-only; changes can't be saved.".to_owned(),
+        ),
+        Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::ConstraintViolation => (
+            StatusCode::CONFLICT,
+            "Conflicts with existing data".to_owned(),
+        ),
+        // A search query that FTS5 can't parse, e.g. unbalanced quotes.
+        Error::SqliteFailure(_, Some(msg)) if msg.starts_with("fts5:") => (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid search query ({})", msg),
+        ),
+        _ => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Database Error".to_owned(),
+        ),
+    }
+}
+
+fn convert_timestamp(timestamp: i64) -> Result<DateTime<Utc>, AppError> {
+    use chrono::{LocalResult, TimeZone};
+
+    match Utc.timestamp_opt(timestamp, 0) {
+        LocalResult::Single(t) => Ok(t),
+        LocalResult::None | LocalResult::Ambiguous(_, _) => Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Invalid timestamp: {}", timestamp),
+        )),
+    }
+}
+
+fn convert_parse_error(err: chrono::ParseError) -> AppError {
+    error!("{:?}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Date format conversion error".to_owned(),
+    )
+}
+
+fn convert_render_error(err: askama::Error) -> AppError {
+    error!("rendering template: {:?}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Template rendering error".to_owned(),
+    )
+}
+
+/// How many entries the index lists.
+const RECENT_ENTRIES: usize = 8;
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexViewModel {
+    site: Site,
+    recent: Vec<IndexEntry>,
+    order: EntryOrder,
+    year_counts: Vec<(u32, u32)>,
+    this_week: WritingSummary,
+    this_month: WritingSummary,
+}
+
+/// A recent entry on the index, with the start of its text.
+struct IndexEntry {
+    entry: Entry,
+    preview: String,
+    /// Whether `preview` stops short of the whole entry.
+    truncated: bool,
+}
+
+impl IndexEntry {
+    fn new(entry: Entry, max_chars: usize) -> Self {
+        let (preview, truncated) = preview(&entry.body, max_chars);
+        IndexEntry {
+            entry,
+            preview,
+            truncated,
+        }
+    }
+}
+
+/// The first `max_chars` characters of an entry's text without its markup, cut back to a
+/// word boundary, and whether there was more.
+fn preview(markdown: &str, max_chars: usize) -> (String, bool) {
+    if max_chars == 0 {
+        return (String::new(), false);
+    }
+    let plain = markdown_to_plain(markdown);
+    let plain = plain.trim();
+    let Some((end, _)) = plain.char_indices().nth(max_chars) else {
+        return (plain.to_owned(), false);
+    };
+    let cut = plain[..end].rfind(char::is_whitespace).unwrap_or(end);
+    (format!("{}...", plain[..cut].trim_end()), true)
+}
+
+/// How much was written over some period.
+#[derive(Default)]
+struct WritingSummary {
+    entries: u32,
+    words: usize,
+}
+
+impl WritingSummary {
+    /// Summarize the current calendar week (starting on `week_start`) and month, going by
+    /// the local dates entries were written on.
+    fn this_week_and_month(
+        cxn: &mut rusqlite::Connection,
+        week_start: chrono::Weekday,
+    ) -> Result<(WritingSummary, WritingSummary), AppError> {
+        use chrono::{Datelike, Local};
+
+        const QUERY: &str = r#"
+            SELECT date, decrypt_body(body)
+            FROM entries
+            WHERE date >= ?
+        "#;
+        let today = Local::now().date_naive();
+        let days_into_week =
+            (7 + today.weekday().num_days_from_monday() - week_start.num_days_from_monday()) % 7;
+        let week_start = today - chrono::Duration::days(days_into_week as i64);
+        let month_start = today.with_day(1).unwrap_or(today);
+        let since = week_start.min(month_start).format("%Y-%m-%d").to_string();
+
+        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let rows = qry
+            .query_map([since], |r| {
+                Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?))
+            })
+            .map_err(convert_db_error)?;
+        let mut week = WritingSummary::default();
+        let mut month = WritingSummary::default();
+        for row in rows {
+            let (date, body) = row.map_err(convert_db_error)?;
+            // Malformed dates can compare after `since` as strings; they aren't this week.
+            let Ok(date) = NaiveDate::parse_from_str(&date, "%Y-%m-%d") else {
+                continue;
+            };
+            let words = body.split_whitespace().count();
+            for (start, summary) in [(week_start, &mut week), (month_start, &mut month)] {
+                if date >= start {
+                    summary.entries += 1;
+                    summary.words += words;
+                }
+            }
+        }
+        Ok((week, month))
+    }
+}
+
+/// Which end of the diary a list of entries starts from.
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EntryOrder {
+    #[default]
+    Newest,
+    Oldest,
+}
+
+impl EntryOrder {
+    /// The `ORDER BY` clause for entries in this order.
+    fn sql(self) -> &'static str {
+        match self {
+            EntryOrder::Newest => "ORDER BY timestamp DESC, rowid DESC",
+            EntryOrder::Oldest => "ORDER BY timestamp ASC, rowid ASC",
+        }
+    }
+}
+
+impl Entry {
+    /// The first `count` entries in `order`.
+    fn recent(
+        cxn: &mut rusqlite::Connection,
+        count: usize,
+        order: EntryOrder,
+    ) -> Result<Vec<Entry>, AppError> {
+        let query = format!(
+            r#"
+            SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+            FROM entries
+            {}
+            LIMIT ?
+            "#,
+            order.sql()
+        );
+        let mut qry = cxn.prepare(&query).map_err(convert_db_error)?;
+        let mut entries = Vec::new();
+        let results = qry
+            .query_map([count], RawEntry::from_row)
+            .map_err(convert_db_error)?;
+        for raw in results {
+            let raw = raw.map_err(convert_db_error)?;
+            let id = raw.id;
+            // Like `year_counts`, don't let one bad row take down the index.
+            match raw.try_into() {
+                Ok(entry) => entries.push(entry),
+                Err((_, msg)) => warn!("Skipping entry {} in recent entries: {}", id, msg),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+type ConnectionArcMux = Arc<tokio::sync::Mutex<rusqlite::Connection>>;
+
+const DEFAULT_DB_LOCK_TIMEOUT_MS: u64 = 5000;
+
+/// Lock the connection, giving up with 503 if another request holds it for longer than
+/// `--db-lock-timeout-ms`. Waiting yields to the runtime, so the request holding the lock
+/// can finish.
+async fn lock_db<'a>(
+    cxn_arcmux: &'a ConnectionArcMux,
+    site: &SiteConfig,
+) -> std::result::Result<tokio::sync::MutexGuard<'a, rusqlite::Connection>, AppError> {
+    match tokio::time::timeout(site.db_lock_timeout, cxn_arcmux.lock()).await {
+        Ok(guard) => Ok(guard),
+        Err(_) => {
+            warn!(
+                "Gave up waiting {:?} for the database",
+                site.db_lock_timeout
+            );
+            Err((
+                StatusCode::SERVICE_UNAVAILABLE,
+                String::from("The diary is busy; try again in a moment"),
+            ))
+        }
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct IndexQuery {
+    #[serde(default)]
+    order: EntryOrder,
+}
+
+async fn get_index(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageQuery(query): PageQuery<IndexQuery>,
+) -> Response {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let recent = Entry::recent(&mut cxn, RECENT_ENTRIES, query.order)?
+        .into_iter()
+        .map(|entry| IndexEntry::new(entry, site.index_preview_length))
+        .collect();
+    let year_counts = year_counts(&mut cxn)?;
+    let (this_week, this_month) = WritingSummary::this_week_and_month(&mut cxn, site.week_start)?;
+    let vm = IndexViewModel {
+        site,
+        recent,
+        order: query.order,
+        year_counts,
+        this_week,
+        this_month,
+    };
+    render(vm)
+}
+
+#[derive(Template)]
+#[template(path = "new.html")]
+struct NewEntryViewModel {
+    site: Site,
+    draft: String,
+    mood: Option<String>,
+    /// Whether `draft` is left over from an earlier visit, rather than a fresh page.
+    recovered: bool,
+    /// When the recovered draft was last saved, if known.
+    saved_at: Option<DateTime<Utc>>,
+    /// Today's journaling prompt, if prompts are configured.
+    prompt: Option<String>,
+    /// Drafts autosaved by tabs' sessions. The page can't tell which, if any, is this
+    /// tab's, so the editor corrects this once it has checked.
+    other_drafts: u32,
+}
+
+impl NewEntryViewModel {
+    fn moods(&self) -> &'static [&'static str] {
+        MOODS
+    }
+
+    fn is_selected(&self, mood: &str) -> bool {
+        self.mood.as_deref() == Some(mood)
+    }
+}
+
+/// The new entry page, with the draft saved without a session (from before drafts had
+/// sessions, or without the editor script). The editor restores its own tab's draft.
+async fn get_new_entry(Extension(cxn_arcmux): Extension<ConnectionArcMux>, site: Site) -> Response {
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let draft = get_draft(&cxn, "")?.unwrap_or_default();
+    let vm = NewEntryViewModel {
+        other_drafts: count_drafts(&cxn, "")?,
+        recovered: !draft.body.trim().is_empty(),
+        draft: draft.body,
+        mood: None,
+        saved_at: draft.saved_at,
+        prompt: site
+            .prompt(chrono::Local::now().date_naive())
+            .map(String::from),
+        site,
+    };
+    render(vm)
+}
+
+/// Start a new entry from a copy of an existing one. The copy isn't saved as the draft
+/// until it's edited, so an abandoned clone doesn't replace the current draft.
+async fn get_clone_entry(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+) -> Response {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    let vm = NewEntryViewModel {
+        site,
+        draft: entry.body,
+        mood: entry.mood,
+        recovered: false,
+        saved_at: None,
+        prompt: None,
+        other_drafts: 0,
+    };
+    render(vm)
+}
+
+#[derive(serde::Deserialize)]
+struct NewEntry {
+    body: String,
+    /// One of `MOODS`, or empty for no mood.
+    #[serde(default)]
+    mood: String,
+    /// The editor's draft session, whose draft is cleared once the entry is saved.
+    #[serde(default)]
+    session: String,
+    /// Whether the page was given the sessionless draft, which is then cleared too.
+    #[serde(default)]
+    adopt: bool,
+}
+
+fn clear_entry_drafts(cxn: &Connection, newentry: &NewEntry) -> Result<(), AppError> {
+    clear_draft(cxn, &newentry.session)?;
+    if newentry.adopt {
+        clear_draft(cxn, "")?;
+    }
+    Ok(())
+}
+
+fn parse_mood(mood: &str) -> Result<Option<&str>, AppError> {
+    match mood {
+        "" => Ok(None),
+        mood if MOODS.contains(&mood) => Ok(Some(mood)),
+        mood => Err((StatusCode::BAD_REQUEST, format!("Unknown mood: {}", mood))),
+    }
+}
+
+async fn post_new_entry(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageForm(newentry): PageForm<NewEntry>,
+) -> Result<Redirect, AppError> {
+    site.check_writable()?;
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let mood = parse_mood(&newentry.mood)?;
+    if let Some(existing) = recent_duplicate(&cxn, &newentry.body, site.duplicate_window_secs)? {
+        info!("Not saving a duplicate of entry {}", existing);
+        clear_entry_drafts(&cxn, &newentry)?;
+        return Ok(Redirect::to(&site.url(&format!("/entry/{}", existing))));
+    }
+    let new_entry_id = create_entry(&cxn, &newentry.body, mood)?;
+    clear_entry_drafts(&cxn, &newentry)?;
+    site.invalidate_stats();
+    let new_item_url = site.url(&format!("/entry/{}", new_entry_id));
+    Ok(Redirect::to(&new_item_url))
+}
+
+/// Save a new entry and add it to the search index, returning its id.
+fn create_entry(
+    cxn: &rusqlite::Connection,
+    body: &str,
+    mood: Option<&str>,
+) -> Result<u32, AppError> {
+    const CREATE: &str = r#"
+        INSERT INTO entries (timestamp, date, body, mood, updated_at)
+        VALUES (unixepoch('now'), date('now', 'localtime'), encrypt_body($1), $2, unixepoch('now'))
+        RETURNING rowid
+    "#;
+    const INDEX: &str = r#"
+        INSERT INTO entrytext (rowid, body) VALUES ($1, $2)
+    "#;
+    let id: u32 = cxn
+        .query_row(CREATE, rusqlite::params![body, mood], |r| r.get(0))
+        .map_err(convert_db_error)?;
+    cxn.execute(INDEX, rusqlite::params![id, body])
+        .map_err(convert_db_error)?;
+    Ok(id)
+}
+
+#[derive(serde::Deserialize)]
+struct ApiNewEntry {
+    body: String,
+    mood: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct CreatedEntry {
+    id: u32,
+    url: String,
+}
+
+/// Create an entry from a script. With an `Idempotency-Key` header, retrying a request
+/// returns the entry the first attempt created instead of saving another copy; keys are
+/// remembered for `--idempotency-window-secs`.
+async fn post_api_entry(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    headers: axum::http::HeaderMap,
+    ApiJson(newentry): ApiJson<ApiNewEntry>,
+) -> Result<(StatusCode, Json<CreatedEntry>), ApiError> {
+    site.check_writable()?;
+    let mood = parse_mood(newentry.mood.as_deref().unwrap_or(""))?;
+    let key = match headers.get("idempotency-key") {
+        Some(key) => Some(key.to_str().map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                String::from("Idempotency-Key must be ASCII"),
+            )
+        })?),
+        None => None,
+    };
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let created = |id: u32| CreatedEntry {
+        id,
+        url: site.url(&format!("/entry/{}", id)),
+    };
+
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    if let Some(key) = key {
+        const EXPIRE: &str = r#"
+            DELETE FROM idempotency_keys WHERE created_at < unixepoch('now') - $1
+        "#;
+        const FIND: &str = r#"
+            SELECT entry_id FROM idempotency_keys WHERE key = $1
+        "#;
+        tx.execute(EXPIRE, [site.idempotency_window_secs])
+            .map_err(convert_db_error)?;
+        let existing: Option<u32> = tx
+            .query_row(FIND, [key], |r| r.get(0))
+            .optional()
+            .map_err(convert_db_error)?;
+        if let Some(id) = existing {
+            tx.commit().map_err(convert_db_error)?;
+            return Ok((StatusCode::OK, Json(created(id))));
+        }
+    }
+    let id = create_entry(&tx, &newentry.body, mood)?;
+    if let Some(key) = key {
+        const REMEMBER: &str = r#"
+            INSERT INTO idempotency_keys (key, entry_id, created_at)
+            VALUES ($1, $2, unixepoch('now'))
+        "#;
+        tx.execute(REMEMBER, rusqlite::params![key, id])
+            .map_err(convert_db_error)?;
+    }
+    tx.commit().map_err(convert_db_error)?;
+    site.invalidate_stats();
+    Ok((StatusCode::CREATED, Json(created(id))))
+}
+
+/// An entry with exactly this body saved in the last `window_secs` seconds, which is most
+/// likely the same form submitted twice.
+fn recent_duplicate(
+    cxn: &rusqlite::Connection,
+    body: &str,
+    window_secs: u32,
+) -> Result<Option<u32>, AppError> {
+    if window_secs == 0 {
+        return Ok(None);
+    }
+    // Materialized so that only entries inside the window are decrypted; SQLite is free
+    // to evaluate a plain `WHERE`'s terms in either order.
+    const QUERY: &str = r#"
+        WITH recent AS MATERIALIZED (
+            SELECT rowid, body FROM entries WHERE timestamp >= unixepoch('now') - ?2
+        )
+        SELECT rowid FROM recent
+        WHERE decrypt_body(body) = ?1
+        ORDER BY rowid DESC
+        LIMIT 1
+    "#;
+    cxn.query_row(QUERY, rusqlite::params![body, window_secs], |r| r.get(0))
+        .optional()
+        .map_err(convert_db_error)
+}
+
+#[derive(Template)]
+#[template(path = "edit.html")]
+struct EditEntryViewModel {
+    site: Site,
+    id: u32,
+    date: NaiveDate,
+    body: String,
+    mood: Option<String>,
+    updated_at: i64,
+    /// The text the writer tried to save when the entry had changed since they loaded it.
+    conflict: Option<String>,
+}
+
+impl EditEntryViewModel {
+    fn new(site: Site, entry: Entry) -> Self {
+        EditEntryViewModel {
+            site,
+            id: entry.id,
+            date: entry.date,
+            body: entry.body,
+            mood: entry.mood,
+            updated_at: entry.updated_at,
+            conflict: None,
+        }
+    }
+
+    fn moods(&self) -> &'static [&'static str] {
+        MOODS
+    }
+
+    fn is_selected(&self, mood: &str) -> bool {
+        self.mood.as_deref() == Some(mood)
+    }
+}
+
+async fn get_edit_entry(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+) -> Response {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    check_unlocked(&cxn, rowid)?;
+    render(EditEntryViewModel::new(site, entry))
+}
+
+#[derive(serde::Deserialize)]
+struct EditEntry {
+    body: String,
+    #[serde(default)]
+    mood: String,
+    /// The entry's `updated_at` when the form was loaded.
+    updated_at: i64,
+}
+
+async fn post_edit_entry(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+    PageForm(edit): PageForm<EditEntry>,
+) -> Result<axum::response::Response, AppError> {
+    use axum::response::IntoResponse;
+
+    site.check_writable()?;
+    let mood = parse_mood(&edit.mood)?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    // `updated_at` always increases, even for two edits in the same second, so a stale
+    // form can never match it.
+    const UPDATE: &str = r#"
+        UPDATE entries
+        SET body = encrypt_body($1), mood = $2, updated_at = MAX(updated_at + 1, unixepoch('now'))
+        WHERE rowid = $3 AND updated_at = $4
+    "#;
+    const INDEX: &str = r#"
+        UPDATE entrytext SET body = $1 WHERE rowid = $2
+    "#;
+    check_unlocked(&cxn, rowid)?;
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    record_history(&tx, rowid, "edit")?;
+    record_revision(&tx, rowid)?;
+    let updated = tx
+        .execute(
+            UPDATE,
+            rusqlite::params![&edit.body, mood, rowid, edit.updated_at],
+        )
+        .map_err(convert_db_error)?;
+    if updated == 0 {
+        drop(tx);
+        let entry = Entry::try_fetch(&mut cxn, rowid)?;
+        let mut vm = EditEntryViewModel::new(site, entry);
+        vm.conflict = Some(edit.body);
+        return Ok((StatusCode::CONFLICT, render(vm)?).into_response());
+    }
+    tx.execute(INDEX, rusqlite::params![&edit.body, rowid])
+        .map_err(convert_db_error)?;
+    tx.commit().map_err(convert_db_error)?;
+    site.invalidate_stats();
+    Ok(Redirect::to(&site.url(&format!("/entry/{}", rowid))).into_response())
+}
+
+/// How many past actions `POST /undo` can step back through.
+const HISTORY_LIMIT: u32 = 20;
+
+/// Save an entry's current body and mood so `action` can be undone.
+fn record_history(cxn: &rusqlite::Connection, entry_id: u32, action: &str) -> Result<(), AppError> {
+    const RECORD: &str = r#"
+        INSERT INTO history (entry_id, action, body, mood, created_at)
+        SELECT rowid, $1, body, mood, unixepoch('now') FROM entries WHERE rowid = $2
+    "#;
+    const TRIM: &str = r#"
+        DELETE FROM history
+        WHERE rowid NOT IN (SELECT rowid FROM history ORDER BY rowid DESC LIMIT $1)
+    "#;
+    cxn.execute(RECORD, rusqlite::params![action, entry_id])
+        .map_err(convert_db_error)?;
+    cxn.execute(TRIM, [HISTORY_LIMIT])
+        .map_err(convert_db_error)?;
+    Ok(())
+}
+
+/// Keep a permanent copy of an entry's body before it's changed.
+fn record_revision(cxn: &rusqlite::Connection, entry_id: u32) -> Result<(), AppError> {
+    const RECORD: &str = r#"
+        INSERT INTO revisions (entry_id, body, edited_at)
+        SELECT rowid, body, unixepoch('now') FROM entries WHERE rowid = $1
+    "#;
+    cxn.execute(RECORD, [entry_id]).map_err(convert_db_error)?;
+    Ok(())
+}
+
+/// Whether an entry has been locked against changes.
+fn is_locked(cxn: &rusqlite::Connection, rowid: u32) -> Result<bool, AppError> {
+    const QUERY: &str = r#"
+        SELECT locked FROM entries WHERE rowid = ?
+    "#;
+    cxn.query_row(QUERY, [rowid], |r| r.get(0))
+        .map_err(convert_db_error)
+}
+
+/// Called by every handler that changes an existing entry.
+fn check_unlocked(cxn: &rusqlite::Connection, rowid: u32) -> Result<(), AppError> {
+    if is_locked(cxn, rowid)? {
+        return Err((
+            StatusCode::FORBIDDEN,
+            format!("Entry {} is locked; unlock it before changing it.", rowid),
+        ));
+    }
+    Ok(())
+}
+
+/// Lock an entry against edits, or unlock it.
+async fn post_toggle_lock(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+) -> Result<Redirect, AppError> {
+    const TOGGLE: &str = r#"
+        UPDATE entries SET locked = NOT locked WHERE rowid = ?
+        RETURNING locked
+    "#;
+    site.check_writable()?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    let locked: bool = tx
+        .query_row(TOGGLE, [rowid], |r| r.get(0))
+        .map_err(convert_db_error)?;
+    let (action, verb) = if locked {
+        ("lock", "Locked")
+    } else {
+        ("unlock", "Unlocked")
+    };
+    record_admin_action(&tx, action, &format!("{} entry {}", verb, rowid))
+        .map_err(convert_db_error)?;
+    tx.commit().map_err(convert_db_error)?;
+    Ok(Redirect::to(&site.url(&format!("/entry/{}", rowid))))
+}
+
+#[derive(serde::Deserialize)]
+struct ToggleTask {
+    /// Which checkbox, counting from 0 in the order they appear on the entry page.
+    task: usize,
+    /// The entry's `updated_at` when the page was loaded.
+    updated_at: i64,
+}
+
+#[derive(serde::Serialize)]
+struct ToggledTask {
+    checked: bool,
+    updated_at: i64,
+}
+
+/// Tick or untick a task list checkbox by rewriting its `[ ]` or `[x]` in the entry.
+async fn post_toggle_task(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiPath(rowid): ApiPath<u32>,
+    ApiForm(toggle): ApiForm<ToggleTask>,
+) -> Result<Json<ToggledTask>, ApiError> {
+    site.check_writable()?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    check_unlocked(&cxn, rowid)?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    // Checkboxes are found by position, so after another edit the number could point at a
+    // different task.
+    if entry.updated_at != toggle.updated_at {
+        return Err((
+            StatusCode::CONFLICT,
+            "The entry has changed since this page was loaded; reload it and try again.".to_owned(),
+        )
+            .into());
+    }
+    let marker = task_markers(&entry.body)
+        .into_iter()
+        .nth(toggle.task)
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            format!("Entry {} has no task {}", rowid, toggle.task),
+        ))?;
+    let checked = &entry.body[marker.clone()] == "[ ]";
+    let mut body = entry.body;
+    body.replace_range(marker, if checked { "[x]" } else { "[ ]" });
+
+    const UPDATE: &str = r#"
+        UPDATE entries
+        SET body = encrypt_body($1), updated_at = MAX(updated_at + 1, unixepoch('now'))
+        WHERE rowid = $2
+        RETURNING updated_at
+    "#;
+    const INDEX: &str = r#"
+        UPDATE entrytext SET body = $1 WHERE rowid = $2
+    "#;
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    record_history(&tx, rowid, "toggle task")?;
+    record_revision(&tx, rowid)?;
+    let updated_at = tx
+        .query_row(UPDATE, rusqlite::params![&body, rowid], |r| r.get(0))
+        .map_err(convert_db_error)?;
+    tx.execute(INDEX, rusqlite::params![&body, rowid])
+        .map_err(convert_db_error)?;
+    tx.commit().map_err(convert_db_error)?;
+    site.invalidate_stats();
+    Ok(Json(ToggledTask {
+        checked,
+        updated_at,
+    }))
+}
+
+/// The history row `POST /undo` would reverse, and the entry it would change.
+fn last_action(cxn: &rusqlite::Connection) -> Result<Option<(i64, u32)>, AppError> {
+    const QUERY: &str = r#"
+        SELECT rowid, entry_id FROM history ORDER BY rowid DESC LIMIT 1
+    "#;
+    cxn.query_row(QUERY, [], |r| Ok((r.get(0)?, r.get(1)?)))
+        .optional()
+        .map_err(convert_db_error)
+}
+
+#[derive(serde::Deserialize)]
+struct Undo {
+    /// The history row the page offered to undo.
+    action: i64,
+}
+
+/// Reverse the most recent edit by restoring the entry's previous body and mood.
+///
+/// There's a single history for the whole diary, since the diary has a single writer. The
+/// page says which edit it offered to undo, so an edit made since (in another tab, or a
+/// double-click) gets a 409 rather than a different edit being undone.
+async fn post_undo(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageForm(undo): PageForm<Undo>,
+) -> Result<Redirect, AppError> {
+    site.check_writable()?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    const LAST: &str = r#"
+        SELECT rowid, entry_id, decrypt_body(body) FROM history ORDER BY rowid DESC LIMIT 1
+    "#;
+    const RESTORE: &str = r#"
+        UPDATE entries
+        SET body = history.body, mood = history.mood,
+            updated_at = MAX(entries.updated_at + 1, unixepoch('now'))
+        FROM history
+        WHERE history.rowid = $1 AND entries.rowid = history.entry_id
+    "#;
+    const INDEX: &str = r#"
+        UPDATE entrytext SET body = $1 WHERE rowid = $2
+    "#;
+    const FORGET: &str = r#"
+        DELETE FROM history WHERE rowid = $1
+    "#;
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    let (action_id, entry_id, body): (i64, u32, String) = tx
+        .query_row(LAST, [], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))
+        .optional()
+        .map_err(convert_db_error)?
+        .ok_or((StatusCode::NOT_FOUND, String::from("Nothing to undo")))?;
+    if action_id != undo.action {
+        return Err((
+            StatusCode::CONFLICT,
+            String::from("The diary has been edited since this page was loaded; reload it to see what undo would change."),
+        ));
+    }
+    check_unlocked(&tx, entry_id)?;
+    record_revision(&tx, entry_id)?;
+    tx.execute(RESTORE, [action_id]).map_err(convert_db_error)?;
+    tx.execute(INDEX, rusqlite::params![body, entry_id])
+        .map_err(convert_db_error)?;
+    tx.execute(FORGET, [action_id]).map_err(convert_db_error)?;
+    tx.commit().map_err(convert_db_error)?;
+    site.invalidate_stats();
+    Ok(Redirect::to(&site.url(&format!("/entry/{}", entry_id))))
+}
+
+#[derive(Template)]
+#[template(path = "entry.html")]
+struct EntryViewModel {
+    site: Site,
+    id: u32,
+    date: NaiveDate,
+    timestamp: DateTime<FixedOffset>,
+    body: String,
+    /// The entry's markdown, before `body` is rendered.
+    source: String,
+    mood: Option<String>,
+    /// The history row `POST /undo` would reverse, if it's a change to this entry.
+    undo_action: Option<i64>,
+    locked: bool,
+    /// Sent back when a task is ticked, to catch edits made since the page was loaded.
+    updated_at: i64,
+    /// The language the entry is written in, falling back to the site's `--locale`.
+    lang: &'static str,
+    /// When in the day the entry was written, e.g. "in the evening".
+    time_of_day: &'static str,
+}
+
+impl EntryViewModel {
+    fn new(site: Site, entry: Entry, offset: FixedOffset) -> Self {
+        use chrono::Timelike;
+
+        let lang = site.locale.tag();
+        let timestamp = entry.timestamp.with_timezone(&offset);
+        EntryViewModel {
+            site,
+            id: entry.id,
+            date: entry.date,
+            time_of_day: time_of_day(timestamp.hour()),
+            timestamp,
+            source: entry.body.clone(),
+            body: entry.body,
+            mood: entry.mood,
+            undo_action: None,
+            locked: false,
+            updated_at: entry.updated_at,
+            lang,
+        }
+    }
+}
+
+/// A phrase for the part of the day an hour (0-23) falls in.
+fn time_of_day(hour: u32) -> &'static str {
+    match hour {
+        5..=11 => "in the morning",
+        12..=16 => "in the afternoon",
+        17..=20 => "in the evening",
+        _ => "at night",
+    }
+}
+
+/// The BCP 47 tag of the language an entry is written in, if it can be told with
+/// confidence. Short entries and ones mixing languages often can't.
+fn detect_language(markdown: &str) -> Option<&'static str> {
+    use whatlang::Lang;
+
+    let info = whatlang::detect(&markdown_to_plain(markdown))?;
+    if !info.is_reliable() {
+        return None;
+    }
+    let tag = match info.lang() {
+        Lang::Afr => "af",
+        Lang::Aka => "ak",
+        Lang::Amh => "am",
+        Lang::Ara => "ar",
+        Lang::Aze => "az",
+        Lang::Bel => "be",
+        Lang::Ben => "bn",
+        Lang::Bul => "bg",
+        Lang::Cat => "ca",
+        Lang::Ces => "cs",
+        Lang::Cmn => "zh",
+        Lang::Dan => "da",
+        Lang::Deu => "de",
+        Lang::Ell => "el",
+        Lang::Eng => "en",
+        Lang::Epo => "eo",
+        Lang::Est => "et",
+        Lang::Fin => "fi",
+        Lang::Fra => "fr",
+        Lang::Guj => "gu",
+        Lang::Heb => "he",
+        Lang::Hin => "hi",
+        Lang::Hrv => "hr",
+        Lang::Hun => "hu",
+        Lang::Hye => "hy",
+        Lang::Ind => "id",
+        Lang::Ita => "it",
+        Lang::Jav => "jv",
+        Lang::Jpn => "ja",
+        Lang::Kan => "kn",
+        Lang::Kat => "ka",
+        Lang::Khm => "km",
+        Lang::Kor => "ko",
+        Lang::Lat => "la",
+        Lang::Lav => "lv",
+        Lang::Lit => "lt",
+        Lang::Mal => "ml",
+        Lang::Mar => "mr",
+        Lang::Mkd => "mk",
+        Lang::Mya => "my",
+        Lang::Nep => "ne",
+        Lang::Nld => "nl",
+        Lang::Nob => "nb",
+        Lang::Ori => "or",
+        Lang::Pan => "pa",
+        Lang::Pes => "fa",
+        Lang::Pol => "pl",
+        Lang::Por => "pt",
+        Lang::Ron => "ro",
+        Lang::Rus => "ru",
+        Lang::Sin => "si",
+        Lang::Slk => "sk",
+        Lang::Slv => "sl",
+        Lang::Sna => "sn",
+        Lang::Spa => "es",
+        Lang::Srp => "sr",
+        Lang::Swe => "sv",
+        Lang::Tam => "ta",
+        Lang::Tel => "te",
+        Lang::Tgl => "tl",
+        Lang::Tha => "th",
+        Lang::Tuk => "tk",
+        Lang::Tur => "tr",
+        Lang::Ukr => "uk",
+        Lang::Urd => "ur",
+        Lang::Uzb => "uz",
+        Lang::Vie => "vi",
+        Lang::Yid => "yi",
+        Lang::Zul => "zu",
+    };
+    Some(tag)
+}
+
+#[derive(serde::Deserialize)]
+struct EntryQuery {
+    /// The viewer's offset from UTC in minutes (e.g. -300 for UTC-5), set by the entry page.
+    tz_offset: Option<i32>,
+}
+
+impl EntryQuery {
+    /// The offset to show timestamps in, falling back to the server's local timezone.
+    fn offset(&self, timestamp: DateTime<Utc>) -> Result<FixedOffset, AppError> {
+        use chrono::{Local, Offset};
+
+        match self.tz_offset {
+            Some(minutes) => parse_tz_offset(minutes),
+            None => Ok(timestamp.with_timezone(&Local).offset().fix()),
+        }
+    }
+}
+
+/// A `tz_offset` query parameter, in minutes east of UTC, as an offset. Anything a day or
+/// more from UTC is a 400.
+fn parse_tz_offset(minutes: i32) -> Result<FixedOffset, AppError> {
+    minutes
+        .checked_mul(60)
+        .and_then(FixedOffset::east_opt)
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid timezone offset: {}", minutes),
+        ))
+}
+
+/// The representations of an entry that `GET /entry/:rowid` can answer with.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum EntryFormat {
+    Html,
+    Json,
+    Markdown,
+}
+
+impl EntryFormat {
+    /// The format the `Accept` header prefers, by quality and then by order. Anything
+    /// unrecognised, including no header at all, gets the page.
+    fn requested(headers: &axum::http::HeaderMap) -> Self {
+        let Some(accept) = headers
+            .get(axum::http::header::ACCEPT)
+            .and_then(|accept| accept.to_str().ok())
+        else {
+            return EntryFormat::Html;
+        };
+        let mut best = (EntryFormat::Html, 0.0);
+        for range in accept.split(',') {
+            let mut params = range.split(';').map(str::trim);
+            let format = match params.next().unwrap_or_default() {
+                "application/json" => EntryFormat::Json,
+                "text/markdown" => EntryFormat::Markdown,
+                "text/html" | "text/*" | "*/*" => EntryFormat::Html,
+                _ => continue,
+            };
+            let quality = params
+                .find_map(|param| param.strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+        best.0
+    }
+}
+
+/// An entry's page, or its JSON or markdown when the `Accept` header asks for them. Errors
+/// are sent as an `ApiError` when JSON was asked for.
+async fn get_entry(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    rowid: Result<Path<u32>, axum::extract::rejection::PathRejection>,
+    query: Result<Query<EntryQuery>, axum::extract::rejection::QueryRejection>,
+    headers: axum::http::HeaderMap,
+) -> axum::response::Response {
+    use axum::response::IntoResponse;
+
+    let format = EntryFormat::requested(&headers);
+    let response = entry_response(&cxn_arcmux, site, rowid, query, format).await;
+    match (response, format) {
+        (Ok(response), _) => response,
+        (Err(err), EntryFormat::Json) => ApiError(err).into_response(),
+        (Err(err), _) => err.into_response(),
+    }
+}
+
+async fn entry_response(
+    cxn_arcmux: &ConnectionArcMux,
+    site: Site,
+    rowid: Result<Path<u32>, axum::extract::rejection::PathRejection>,
+    query: Result<Query<EntryQuery>, axum::extract::rejection::QueryRejection>,
+    format: EntryFormat,
+) -> Result<axum::response::Response, AppError> {
+    use axum::http::header;
+    use axum::response::IntoResponse;
+
+    let Path(rowid) = rowid.map_err(|rejection| (rejection.status(), rejection.body_text()))?;
+    let Query(query) = query.map_err(query_rejection_error)?;
+    let mut cxn = lock_db(cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    let vary = [(header::VARY, "Accept")];
+    let response = match format {
+        EntryFormat::Json => (vary, Json(entry)).into_response(),
+        EntryFormat::Markdown => (
+            vary,
+            [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            entry.body,
+        )
+            .into_response(),
+        EntryFormat::Html => (vary, entry_page(&cxn, site, entry, &query)?).into_response(),
+    };
+    Ok(response)
+}
+
+fn entry_page(cxn: &Connection, site: Site, entry: Entry, query: &EntryQuery) -> Response {
+    let rowid = entry.id;
+    let offset = query.offset(entry.timestamp)?;
+    let mut entry = EntryViewModel::new(site, entry, offset);
+    entry.lang = detect_language(&entry.source).unwrap_or(entry.site.locale.tag());
+    entry.body = render_markdown(&entry.body, entry.site.markdown);
+    entry.locked = is_locked(cxn, rowid)?;
+    entry.undo_action = match last_action(cxn)? {
+        Some((action, entry_id)) if !entry.locked && entry_id == rowid => Some(action),
+        _ => None,
+    };
+    render(entry)
+}
+
+#[derive(Template)]
+#[template(path = "print.html")]
+struct PrintViewModel {
+    site: Site,
+    date: NaiveDate,
+    timestamp: DateTime<FixedOffset>,
+    body: String,
+    lang: &'static str,
+}
+
+/// An entry on its own page, without the site's navigation, for printing.
+async fn get_entry_print(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+    PageQuery(query): PageQuery<EntryQuery>,
+) -> Response {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    let offset = query.offset(entry.timestamp)?;
+    let vm = PrintViewModel {
+        date: entry.date,
+        timestamp: entry.timestamp.with_timezone(&offset),
+        body: render_markdown(&entry.body, site.markdown),
+        lang: detect_language(&entry.body).unwrap_or(site.locale.tag()),
+        site,
+    };
+    render(vm)
+}
+
+/// An earlier version of an entry.
+struct Revision {
+    id: u32,
+    /// When this version was replaced.
+    edited_at: DateTime<Utc>,
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryViewModel {
+    site: Site,
+    entry_id: u32,
+    date: NaiveDate,
+    revisions: Vec<Revision>,
+}
+
+async fn get_entry_history(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+) -> Response {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    const QUERY: &str = r#"
+        SELECT rowid, edited_at FROM revisions WHERE entry_id = ? ORDER BY rowid DESC
+    "#;
+    let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+    let rows = qry
+        .query_map([rowid], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map_err(convert_db_error)?;
+    let mut revisions = Vec::new();
+    for row in rows {
+        let (id, edited_at): (u32, i64) = row.map_err(convert_db_error)?;
+        revisions.push(Revision {
+            id,
+            edited_at: convert_timestamp(edited_at)?,
+        });
+    }
+    let vm = HistoryViewModel {
+        site,
+        entry_id: rowid,
+        date: entry.date,
+        revisions,
+    };
+    render(vm)
+}
+
+struct DiffLine {
+    /// `insert`, `delete` or `equal`, used as the line's CSS class.
+    kind: &'static str,
+    text: String,
+}
+
+#[derive(Template)]
+#[template(path = "diff.html")]
+struct DiffViewModel {
+    site: Site,
+    entry_id: u32,
+    revision: Revision,
+    lines: Vec<DiffLine>,
+}
+
+/// A line diff from a revision to the entry's current body.
+async fn get_entry_diff(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path((rowid, rev)): Path<(u32, u32)>,
+) -> Response {
+    use similar::{ChangeTag, TextDiff};
+
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    const QUERY: &str = r#"
+        SELECT decrypt_body(body), edited_at FROM revisions WHERE rowid = ? AND entry_id = ?
+    "#;
+    let (old, edited_at): (String, i64) = cxn
+        .query_row(QUERY, [rev, rowid], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map_err(convert_db_error)?;
+    // Only show changes to the text, not to line endings.
+    let normalize = |body: &str| {
+        let mut body = body.replace("\r\n", "\n");
+        if !body.ends_with('\n') {
+            body.push('\n');
+        }
+        body
+    };
+    let (old, new) = (normalize(&old), normalize(&entry.body));
+    let diff = TextDiff::from_lines(&old, &new);
+    let lines = diff
+        .iter_all_changes()
+        .map(|change| DiffLine {
+            kind: match change.tag() {
+                ChangeTag::Insert => "insert",
+                ChangeTag::Delete => "delete",
+                ChangeTag::Equal => "equal",
+            },
+            text: change.value().trim_end_matches('\n').to_owned(),
+        })
+        .collect();
+    let vm = DiffViewModel {
+        site,
+        entry_id: rowid,
+        revision: Revision {
+            id: rev,
+            edited_at: convert_timestamp(edited_at)?,
+        },
+        lines,
+    };
+    render(vm)
+}
+
+/// How entry bodies are turned into HTML.
+#[derive(Clone, Copy)]
+struct MarkdownOptions {
+    /// Skip sanitizing the rendered HTML, keeping things like `<iframe>` embeds. Only safe
+    /// when everyone who can write entries is trusted.
+    trust_html: bool,
+    /// Turn straight quotes, `--` and `...` into curly quotes, dashes and ellipses.
+    smart_punctuation: bool,
+    /// Render TeX in `$...$` and `$$...$$` as MathML.
+    math: bool,
+}
+
+impl Default for MarkdownOptions {
+    fn default() -> Self {
+        MarkdownOptions {
+            trust_html: false,
+            smart_punctuation: true,
+            math: false,
+        }
+    }
+}
+
+/// MathML elements `latex2mathml` produces, and their attributes.
+const MATHML_TAGS: &[&str] = &[
+    "math",
+    "mfrac",
+    "mi",
+    "mmultiscripts",
+    "mn",
+    "mo",
+    "mover",
+    "mroot",
+    "mrow",
+    "mspace",
+    "msqrt",
+    "mstyle",
+    "msub",
+    "msubsup",
+    "msup",
+    "mtable",
+    "mtd",
+    "mtext",
+    "mtr",
+    "munder",
+    "munderover",
+    "semantics",
+];
+const MATHML_ATTRIBUTES: &[&str] = &[
+    "accent",
+    "columnalign",
+    "display",
+    "displaystyle",
+    "form",
+    "mathvariant",
+    "maxsize",
+    "minsize",
+    "stretchy",
+    "width",
+];
+
+fn render_markdown(markdown: &str, options: MarkdownOptions) -> String {
+    use pulldown_cmark::{html::push_html, Event, Options, Parser, Tag};
+
+    fn push_section(
+        unsafe_html: &mut String,
+        section: &str,
+        markdown_options: MarkdownOptions,
+        tasks: &mut usize,
+    ) {
+        let mut options = Options::ENABLE_TASKLISTS;
+        if markdown_options.smart_punctuation {
+            options.insert(Options::ENABLE_SMART_PUNCTUATION);
+        }
+        // Math is swapped for placeholders before parsing, so markdown syntax inside it (like
+        // `*` or `_`) is left alone, and put back once the section is HTML.
+        let (section, math) = if markdown_options.math {
+            replace_math(section)
+        } else {
+            (section.to_owned(), Vec::new())
+        };
+        // The parser splits text at characters like `_`, so runs of text are joined before
+        // looking for URLs. Text that's already a link, an image's alt text, or code is left
+        // alone.
+        let mut events = Vec::new();
+        let mut text = String::new();
+        let mut inside = 0;
+        for event in Parser::new_ext(&section, options) {
+            if let (Event::Text(t), 0) = (&event, inside) {
+                text.push_str(t);
+                continue;
+            }
+            events.extend(autolink(&text));
+            text.clear();
+            // Checkboxes are numbered so the entry page can say which one was ticked; see
+            // `task_markers`.
+            if let Event::TaskListMarker(checked) = event {
+                events.push(Event::Html(
+                    format!(
+                        "<input type=\"checkbox\" data-task=\"{}\" disabled{}>\n",
+                        tasks,
+                        if checked { " checked" } else { "" }
+                    )
+                    .into(),
+                ));
+                *tasks += 1;
+                continue;
+            }
+            match event {
+                Event::Start(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => inside += 1,
+                Event::End(Tag::Link(..) | Tag::Image(..) | Tag::CodeBlock(_)) => inside -= 1,
+                _ => {}
+            }
+            events.push(event);
+        }
+        events.extend(autolink(&text));
+        let mut html = String::new();
+        push_html(&mut html, events.into_iter());
+        for (n, mathml) in math.iter().enumerate() {
+            html = html.replace(&math_placeholder(n), mathml);
+        }
+        unsafe_html.push_str(&html);
+    }
+
+    let mut unsafe_html = String::new();
+    let mut tasks = 0;
+    for block in markdown_blocks(markdown) {
+        match block {
+            MarkdownBlock::Text(_, section) => {
+                push_section(&mut unsafe_html, section, options, &mut tasks)
+            }
+            MarkdownBlock::SpoilerStart(summary) => {
+                let summary = match summary {
+                    "" => "Spoiler",
+                    s => s,
+                };
+                unsafe_html.push_str(&format!(
+                    "<details><summary>{}</summary>\n",
+                    escape_html(summary)
+                ));
+            }
+            MarkdownBlock::SpoilerEnd => unsafe_html.push_str("</details>\n"),
+        }
+    }
+    if options.trust_html {
+        unsafe_html
+    } else {
+        // Task list checkboxes are the only inputs entries may contain, so every input is
+        // made one, whatever its `type` was.
+        let mut sanitizer = ammonia::Builder::default();
+        sanitizer
+            .add_tags(["input"])
+            .add_tag_attributes("input", ["checked", "disabled", "data-task"])
+            .set_tag_attribute_value("input", "type", "checkbox");
+        if options.math {
+            sanitizer.add_tags(MATHML_TAGS);
+            for tag in MATHML_TAGS {
+                sanitizer.add_tag_attributes(tag, MATHML_ATTRIBUTES);
+            }
+        }
+        sanitizer.clean(&unsafe_html).to_string()
+    }
+}
+
+/// Stands in for the `n`th math span while its section is parsed. Private-use characters
+/// mean nothing to markdown and won't turn up in an entry by accident.
+fn math_placeholder(n: usize) -> String {
+    format!("\u{E000}{}\u{E001}", n)
+}
+
+/// `section` with each math span replaced by a `math_placeholder`, and the MathML for each
+/// span in order. TeX that can't be converted is kept as it was written.
+fn replace_math(section: &str) -> (String, Vec<String>) {
+    let mut replaced = String::new();
+    let mut math = Vec::new();
+    let mut rest = 0;
+    for (span, display) in find_math(section) {
+        let delimiter = if display { 2 } else { 1 };
+        let tex = &section[span.start + delimiter..span.end - delimiter];
+        let style = if display {
+            latex2mathml::DisplayStyle::Block
+        } else {
+            latex2mathml::DisplayStyle::Inline
+        };
+        let mathml = latex2mathml::latex_to_mathml(tex, style)
+            .unwrap_or_else(|_| escape_html(&section[span.clone()]));
+        replaced.push_str(&section[rest..span.start]);
+        replaced.push_str(&math_placeholder(math.len()));
+        math.push(mathml);
+        rest = span.end;
+    }
+    replaced.push_str(&section[rest..]);
+    (replaced, math)
+}
+
+/// The byte ranges of `$...$` and `$$...$$` spans in some markdown, and whether each is
+/// display math. Code, raw HTML and escaped `\$`s are skipped. Like Pandoc, inline math
+/// can't start or end with a space, or end just before a digit, so "$5 and $10" stays text.
+fn find_math(markdown: &str) -> Vec<(std::ops::Range<usize>, bool)> {
+    use pulldown_cmark::{Event, Parser, Tag};
+
+    let skip: Vec<std::ops::Range<usize>> = Parser::new(markdown)
+        .into_offset_iter()
+        .filter_map(|(event, range)| {
+            matches!(
+                event,
+                Event::Code(_) | Event::Html(_) | Event::Start(Tag::CodeBlock(_))
+            )
+            .then_some(range)
+        })
+        .collect();
+    let skipped = |i: usize| skip.iter().find(|range| range.contains(&i));
+    let bytes = markdown.as_bytes();
+    let mut spans = Vec::new();
+    let mut i = 0;
+    'scan: while i < bytes.len() {
+        if let Some(range) = skipped(i) {
+            i = range.end;
+            continue;
+        }
+        match bytes[i] {
+            b'\\' => {
+                i += 2;
+                continue;
+            }
+            b'$' => {}
+            _ => {
+                i += 1;
+                continue;
+            }
+        }
+        let display = bytes.get(i + 1) == Some(&b'$');
+        let start = if display { i + 2 } else { i + 1 };
+        if !display && bytes.get(start).is_none_or(u8::is_ascii_whitespace) {
+            i += 1;
+            continue;
+        }
+        let mut j = start;
+        while j < bytes.len() && skipped(j).is_none() {
+            match bytes[j] {
+                b'\\' => j += 2,
+                // Inline math stays on one line, and display math in one paragraph.
+                b'\n' if !display || bytes.get(j + 1) == Some(&b'\n') => break,
+                b'$' if display && bytes.get(j + 1) == Some(&b'$') && j > start => {
+                    spans.push((i..j + 2, true));
+                    i = j + 2;
+                    continue 'scan;
+                }
+                b'$' if !display
+                    && !bytes[j - 1].is_ascii_whitespace()
+                    && !bytes.get(j + 1).is_some_and(u8::is_ascii_digit) =>
+                {
+                    spans.push((i..j + 1, false));
+                    i = j + 1;
+                    continue 'scan;
+                }
+                _ => j += 1,
+            }
+        }
+        i = start;
+    }
+    spans
+}
+
+/// Part of an entry's markdown, split at spoiler markers.
+enum MarkdownBlock<'a> {
+    /// Markdown, and the byte offset in the entry it starts at.
+    Text(usize, &'a str),
+    /// A `:::spoiler Summary` line, with its summary.
+    SpoilerStart(&'a str),
+    /// A `:::` line closing a spoiler, or the end of an entry with unclosed spoilers.
+    SpoilerEnd,
+}
+
+/// `:::spoiler Summary` ... `:::` folds the lines between into a <details> element.
+/// The markdown between markers is parsed on its own so the markers never reach the parser.
+fn markdown_blocks(markdown: &str) -> Vec<MarkdownBlock<'_>> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut open_spoilers = 0;
+    let mut in_code = false;
+    for line in markdown.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+        }
+        if in_code {
+            continue;
+        }
+        let block = if let Some(summary) = trimmed.strip_prefix(":::spoiler") {
+            open_spoilers += 1;
+            MarkdownBlock::SpoilerStart(summary.trim())
+        } else if trimmed == ":::" && open_spoilers > 0 {
+            open_spoilers -= 1;
+            MarkdownBlock::SpoilerEnd
+        } else {
+            continue;
+        };
+        blocks.push(MarkdownBlock::Text(start, &markdown[start..line_start]));
+        blocks.push(block);
+        start = offset;
+    }
+    blocks.push(MarkdownBlock::Text(start, &markdown[start..]));
+    for _ in 0..open_spoilers {
+        blocks.push(MarkdownBlock::SpoilerEnd);
+    }
+    blocks
+}
+
+/// Where each task list checkbox's `[ ]` or `[x]` is in an entry's markdown, numbered the
+/// same way `render_markdown` numbers the checkboxes.
+fn task_markers(markdown: &str) -> Vec<std::ops::Range<usize>> {
+    use pulldown_cmark::{Event, Options, Parser};
+
+    let mut markers = Vec::new();
+    for block in markdown_blocks(markdown) {
+        if let MarkdownBlock::Text(offset, section) = block {
+            let parser = Parser::new_ext(section, Options::ENABLE_TASKLISTS);
+            markers.extend(parser.into_offset_iter().filter_map(|(event, range)| {
+                matches!(event, Event::TaskListMarker(_))
+                    .then(|| offset + range.start..offset + range.end)
+            }));
+        }
+    }
+    markers
+}
+
+/// Split text from a markdown document into plain text and links, turning bare `http://` and
+/// `https://` URLs into links.
+fn autolink(text: &str) -> Vec<pulldown_cmark::Event<'static>> {
+    use pulldown_cmark::Event;
+
+    let mut events = Vec::new();
+    let mut rest = text;
+    while let Some(start) = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        let candidate = &rest[start..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || "<>\"".contains(c))
+            .unwrap_or(candidate.len());
+        // Punctuation at the end of a URL usually belongs to the sentence around it.
+        let mut url = &candidate[..end];
+        loop {
+            let unbalanced =
+                url.ends_with(')') && url.matches('(').count() < url.matches(')').count();
+            if url.ends_with(|c: char| ".,;:!?'*_".contains(c)) || unbalanced {
+                url = &url[..url.len() - 1];
+            } else {
+                break;
+            }
+        }
+        let scheme_len = candidate.find("//").map_or(0, |i| i + 2);
+        if url.len() <= scheme_len {
+            events.push(Event::Text(rest[..start + scheme_len].to_owned().into()));
+            rest = &rest[start + scheme_len..];
+            continue;
+        }
+        if start > 0 {
+            events.push(Event::Text(rest[..start].to_owned().into()));
+        }
+        let href = escape_html(url);
+        events.push(Event::Html(
+            format!("<a href=\"{}\">{}</a>", href, href).into(),
+        ));
+        rest = &candidate[url.len()..];
+    }
+    if !rest.is_empty() {
+        events.push(Event::Text(rest.to_owned().into()));
+    }
+    events
+}
+
+/// The text of a markdown document without its markup: headings, emphasis and links are
+/// flattened to their words, and blocks are separated by blank lines.
+fn markdown_to_plain(markdown: &str) -> String {
+    use pulldown_cmark::{Event, Parser, Tag};
+
+    fn end_block(plain: &mut String, separator: &str) {
+        let trimmed = plain.trim_end_matches('\n').len();
+        plain.truncate(trimmed);
+        plain.push_str(separator);
+    }
+
+    let mut plain = String::new();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Text(text) | Event::Code(text) => plain.push_str(&text),
+            Event::SoftBreak | Event::HardBreak => plain.push('\n'),
+            Event::End(Tag::Item) => end_block(&mut plain, "\n"),
+            Event::End(Tag::Paragraph | Tag::Heading(..) | Tag::CodeBlock(_) | Tag::List(_)) => {
+                end_block(&mut plain, "\n\n")
+            }
+            _ => {}
+        }
+    }
+    end_block(&mut plain, "\n");
+    plain
+}
+
+async fn get_entry_plain(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+) -> Result<String, AppError> {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    Ok(markdown_to_plain(&entry.body))
+}
+
+/// An entry's markdown exactly as it was written.
+async fn get_entry_raw(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(rowid): Path<u32>,
+) -> Result<String, AppError> {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entry = Entry::try_fetch(&mut cxn, rowid)?;
+    Ok(entry.body)
+}
+
+/// Redirect to the newest entry, or to the new entry page if there aren't any yet.
+async fn get_latest(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+) -> Result<Redirect, AppError> {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let url = match Entry::recent(&mut cxn, 1, EntryOrder::Newest)?.first() {
+        Some(entry) => site.url(&format!("/entry/{}", entry.id)),
+        None => site.url("/new"),
+    };
+    Ok(Redirect::to(&url))
+}
+
+/// Redirect to the oldest entry, or to the new entry page if there aren't any yet.
+async fn get_first(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+) -> Result<Redirect, AppError> {
+    const QUERY: &str = r#"
+        SELECT rowid FROM entries ORDER BY timestamp ASC, rowid ASC LIMIT 1
+    "#;
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let first: Option<u32> = cxn
+        .query_row(QUERY, [], |r| r.get(0))
+        .optional()
+        .map_err(convert_db_error)?;
+    let url = match first {
+        Some(id) => site.url(&format!("/entry/{}", id)),
+        None => site.url("/new"),
+    };
+    Ok(Redirect::to(&url))
+}
+
+fn year_counts(cxn: &mut rusqlite::Connection) -> Result<Vec<(u32, u32)>, AppError> {
+    let qry = r#"
+        SELECT
+            strftime('%Y', date) AS year,
+            COUNT(*) as cnt
+        FROM entries
+        GROUP BY year
+        ORDER BY year DESC
+    "#;
+    let mut stmt = cxn.prepare(qry).map_err(convert_db_error)?;
+    let rows = stmt
+        .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map_err(convert_db_error)?;
+    let mut results = Vec::new();
+    for row in rows {
+        // `strftime` gives NULL for dates it can't parse; leave those entries out of the
+        // index rather than failing to show it at all.
+        let (year, count): (Option<String>, u32) = row.map_err(convert_db_error)?;
+        match year.as_deref().map(str::parse) {
+            Some(Ok(year)) => results.push((year, count)),
+            _ => warn!(
+                "Skipping {} entries with an unreadable date: {:?}",
+                count, year
+            ),
+        }
+    }
+    Ok(results)
+}
+
+#[derive(Template, serde::Serialize)]
+#[template(path = "year.html")]
+struct YearViewModel {
+    #[serde(skip)]
+    site: Site,
+    year: u32,
+    months: Vec<MonthSummary>,
+    entry_count: u32,
+    /// Words across every entry in the year, including ones the month lists leave out.
+    word_count: usize,
+}
+
+/// The start of a month's entries, for the year page.
+#[derive(serde::Serialize)]
+struct MonthSummary {
+    month: chrono::Month,
+    entries: Vec<Entry>,
+    /// How many entries the month has, including any left out of `entries`.
+    total: usize,
+}
+
+impl MonthSummary {
+    fn is_truncated(&self) -> bool {
+        self.entries.len() < self.total
+    }
+}
+
+impl Entry {
+    /// The month the entry is filed under, which (like its day) follows its local date.
+    fn month(&self) -> Result<chrono::Month, AppError> {
+        use chrono::prelude::*;
+        use num_traits::FromPrimitive;
+
+        Month::from_u32(self.date.month()).ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Date conversion error".to_string(),
+        ))
+    }
+}
+
+impl Entry {
+    fn in_year(cxn: &mut rusqlite::Connection, year: u32) -> Result<Vec<Entry>, AppError> {
+        const QUERY: &str = r#"
+            SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+            FROM entries
+            WHERE CAST(strftime('%Y', date) AS INTEGER) = ?
+            ORDER BY timestamp
+        "#;
+        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let mut entries = Vec::new();
+        let results = qry
+            .query_map([year], RawEntry::from_row)
+            .map_err(convert_db_error)?;
+        for raw in results {
+            let raw = raw.map_err(convert_db_error)?;
+            entries.push(raw.try_into()?);
+        }
+        Ok(entries)
+    }
+}
+
+impl YearViewModel {
+    fn get(cxn: &mut rusqlite::Connection, site: Site, year: u32) -> Result<Self, AppError> {
+        use chrono::Month;
+        let mut entries: HashMap<chrono::Month, Vec<Entry>> = HashMap::new();
+        let mut entry_count = 0;
+        let mut word_count = 0;
+        for entry in Entry::in_year(cxn, year)? {
+            let month = entry.month()?;
+            word_count += entry.body.split_whitespace().count();
+            if let Some(month_list) = entries.get_mut(&month) {
+                month_list.push(entry);
+            } else {
+                entries.insert(month, vec![entry]);
+            }
+            entry_count += 1;
+        }
+        let mut months: Vec<(Month, Vec<Entry>)> = entries.into_iter().collect();
+        months.sort_by_key(|(m, _)| m.number_from_month());
+        let months = months
+            .into_iter()
+            .map(|(month, mut entries)| {
+                entries.sort_by_key(|e| e.timestamp);
+                let total = entries.len();
+                entries.truncate(site.entries_per_month);
+                MonthSummary {
+                    month,
+                    entries,
+                    total,
+                }
+            })
+            .collect();
+        Ok(YearViewModel {
+            site,
+            year,
+            months,
+            entry_count,
+            word_count,
+        })
+    }
+}
+
+/// Years a diary could plausibly have entries in.
+const YEARS: std::ops::RangeInclusive<u32> = 1900..=2100;
+
+/// Reject years in URLs that can't have entries, before querying for them.
+fn check_year(year: u32) -> Result<(), AppError> {
+    if !YEARS.contains(&year) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Invalid year: {} (must be between {} and {})",
+                year,
+                YEARS.start(),
+                YEARS.end()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// The year page. It's sent with `Last-Modified` and an `ETag`, and answered with 304 when
+/// the browser's copy is still current. The footer's diary-wide stats are on the page too,
+/// so both validators change with any write to the diary, not just to this year; the
+/// `ETag` also covers the read-only banner and asset URLs, which change on restart.
+async fn get_year(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(year): Path<u32>,
+    headers: axum::http::HeaderMap,
+) -> Result<axum::response::Response, AppError> {
+    use axum::http::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, VARY};
+    use axum::http::HeaderValue;
+    use axum::response::IntoResponse;
+
+    // Deleting an entry changes the page too, so deletions count as modifications.
+    const LAST_MODIFIED_QUERY: &str = r#"
+        SELECT MAX(
+            COALESCE((SELECT MAX(COALESCE(updated_at, timestamp)) FROM entries), 0),
+            COALESCE((SELECT MAX(deleted_at) FROM deleted_entries), 0)
+        )
+    "#;
+    check_year(year)?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    check_year_has_entries(&cxn, year)?;
+    let last_modified: i64 = cxn
+        .query_row(LAST_MODIFIED_QUERY, [], |r| r.get(0))
+        .map_err(convert_db_error)?;
+    let (entries, bytes) = site
+        .stats
+        .map_or((0, 0), |stats| (stats.entries, stats.bytes));
+    let etag = format!(
+        "\"{}-{}-{}-{}-{}\"",
+        last_modified, entries, bytes, site.read_only as u8, site.asset_version
+    );
+    // `If-None-Match` is the stronger check, so `If-Modified-Since` is only used without it.
+    let unchanged = match headers.get(IF_NONE_MATCH) {
+        Some(tags) => tags
+            .to_str()
+            .is_ok_and(|tags| tags.split(',').any(|tag| tag.trim() == etag)),
+        None => headers
+            .get(IF_MODIFIED_SINCE)
+            .and_then(|since| since.to_str().ok())
+            .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+            .is_some_and(|since| last_modified <= since.timestamp()),
+    };
+    // The theme cookie changes the page without changing any entries.
+    let vary = (VARY, HeaderValue::from_static("Cookie"));
+    let etag = HeaderValue::from_str(&etag).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Couldn't make an ETag: {}", e),
+        )
+    })?;
+    if unchanged {
+        return Ok((StatusCode::NOT_MODIFIED, [vary, (ETAG, etag)]).into_response());
+    }
+    let vm = YearViewModel::get(&mut cxn, site, year)?;
+    let mut response = ([vary, (ETAG, etag)], render(vm)?).into_response();
+    if let Some(http_date) = http_date(last_modified) {
+        response.headers_mut().insert(LAST_MODIFIED, http_date);
+    }
+    Ok(response)
+}
+
+/// 404 for a year without entries, so its page isn't shown empty.
+fn check_year_has_entries(cxn: &Connection, year: u32) -> Result<(), AppError> {
+    const EXISTS_QUERY: &str = r#"
+        SELECT EXISTS (SELECT 1 FROM entries WHERE strftime('%Y', date) = $1)
+    "#;
+    let exists: bool = cxn
+        .query_row(EXISTS_QUERY, [format!("{:04}", year)], |r| r.get(0))
+        .map_err(convert_db_error)?;
+    if !exists {
+        return Err((StatusCode::NOT_FOUND, format!("No entries in {}", year)));
+    }
+    Ok(())
+}
+
+/// A Unix timestamp formatted for HTTP headers, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(timestamp: i64) -> Option<axum::http::HeaderValue> {
+    use chrono::TimeZone;
+
+    let date = Utc.timestamp_opt(timestamp, 0).single()?;
+    let formatted = date.format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+    axum::http::HeaderValue::from_str(&formatted).ok()
+}
+
+#[derive(Template)]
+#[template(path = "month.html")]
+struct MonthViewModel {
+    site: Site,
+    year: u32,
+    month: chrono::Month,
+    entries: Vec<Entry>,
+}
+
+impl Entry {
+    fn in_month(
+        cxn: &mut rusqlite::Connection,
+        year: u32,
+        month: chrono::Month,
+    ) -> Result<Vec<Entry>, AppError> {
+        const QUERY: &str = r#"
+            SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+            FROM entries
+            WHERE strftime('%Y-%m', date) = ?
+            ORDER BY timestamp
+        "#;
+        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let mut entries = Vec::new();
+        let year_month = format!("{:04}-{:02}", year, month.number_from_month());
+        let results = qry
+            .query_map([year_month], RawEntry::from_row)
+            .map_err(convert_db_error)?;
+        for raw in results {
+            let raw = raw.map_err(convert_db_error)?;
+            entries.push(raw.try_into()?);
+        }
+        Ok(entries)
+    }
+}
+
+async fn get_month(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path((year, month)): Path<(u32, u32)>,
+) -> Response {
+    use num_traits::FromPrimitive;
+
+    check_year(year)?;
+    let month = chrono::Month::from_u32(month)
+        .ok_or((StatusCode::NOT_FOUND, format!("No such month: {}", month)))?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entries = Entry::in_month(&mut cxn, year, month)?;
+    if entries.is_empty() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            format!("No entries in {} {}", month.name(), year),
+        ));
+    }
+    let vm = MonthViewModel {
+        site,
+        year,
+        month,
+        entries,
+    };
+    render(vm)
+}
+
+/// A URL- and filename-safe version of the start of an entry's first line, at most
+/// `MAX_LEN` characters long. Later words are only added whole; a long first word (like
+/// unspaced CJK text or a pasted hash) is cut short.
+fn slug(body: &str) -> String {
+    const MAX_LEN: usize = 40;
+    let first_line = body.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    let mut slug = String::new();
+    let mut len = 0;
+    for word in first_line
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        let word: String = word.chars().flat_map(char::to_lowercase).collect();
+        let word_len = word.chars().count();
+        if len == 0 {
+            slug.extend(word.chars().take(MAX_LEN));
+            len = word_len.min(MAX_LEN);
+            continue;
+        }
+        if len + 1 + word_len > MAX_LEN {
+            break;
+        }
+        slug.push('-');
+        slug.push_str(&word);
+        len += 1 + word_len;
+    }
+    slug
+}
+
+/// An entry as a standalone markdown file, with its metadata in YAML front matter.
+fn entry_markdown(entry: &Entry) -> String {
+    let mut markdown = format!(
+        "---\nid: {}\ndate: {}\ntimestamp: {}\n",
+        entry.id,
+        entry.date,
+        entry.timestamp.to_rfc3339()
+    );
+    if let Some(mood) = &entry.mood {
+        markdown.push_str(&format!("mood: {}\n", mood));
+    }
+    markdown.push_str("---\n\n");
+    markdown.push_str(&entry.body);
+    if !markdown.ends_with('\n') {
+        markdown.push('\n');
+    }
+    markdown
+}
+
+fn convert_zip_error(err: zip::result::ZipError) -> AppError {
+    error!("{:?}", err);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Error building zip file".to_owned(),
+    )
+}
+
+/// A zip of the year's entries as `YYYY-MM-DD-<slug>.md` files. It's built in memory rather
+/// than streamed, because `ZipWriter` seeks back to fill in each file's header once the file
+/// is compressed. Entries are dropped as they're added, so the year's text is only held
+/// about twice over at worst (once decrypted, once compressed).
+async fn get_year_export(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(year): Path<u32>,
+) -> Result<axum::response::Response, AppError> {
+    use axum::http::header;
+    use axum::response::IntoResponse;
+    use chrono::{Datelike, Timelike};
+    use std::io::Write;
+
+    check_year(year)?;
+    let entries = {
+        let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+        Entry::in_year(&mut cxn, year)?
+    };
+    if entries.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("No entries in {}", year)));
+    }
+
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut names = std::collections::HashSet::new();
+    for entry in entries {
+        let slug = slug(&entry.body);
+        let mut name = if slug.is_empty() {
+            format!("{}-{}.md", entry.date, entry.id)
+        } else {
+            format!("{}-{}.md", entry.date, slug)
+        };
+        if !names.insert(name.clone()) {
+            name = format!("{}-{}-{}.md", entry.date, slug, entry.id);
+            names.insert(name.clone());
+        }
+        let written = entry.timestamp.naive_local();
+        let mtime = zip::DateTime::from_date_and_time(
+            written.year() as u16,
+            written.month() as u8,
+            written.day() as u8,
+            written.hour() as u8,
+            written.minute() as u8,
+            written.second() as u8,
+        )
+        .unwrap_or_default();
+        let options = zip::write::FileOptions::default().last_modified_time(mtime);
+        zip.start_file(name, options).map_err(convert_zip_error)?;
+        zip.write_all(entry_markdown(&entry).as_bytes())
+            .map_err(|e| convert_zip_error(e.into()))?;
+    }
+    let bytes = zip.finish().map_err(convert_zip_error)?.into_inner();
+
+    let disposition = format!("attachment; filename=\"diary-{}.zip\"", year);
+    Ok((
+        [
+            (header::CONTENT_TYPE, String::from("application/zip")),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        bytes,
+    )
+        .into_response())
+}
+
+/// Every entry matching a search, newest first, as one markdown document of
+/// `entry_markdown`s. Unlike the search page, this isn't limited by
+/// `--max-search-results`.
+async fn get_search_export(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageQuery(search): PageQuery<SearchQuery>,
+) -> Result<axum::response::Response, AppError> {
+    use axum::http::header;
+    use axum::response::IntoResponse;
+
+    const QUERY: &str = r#"
+        SELECT entries.rowid, date, timestamp, decrypt_body(entries.body), mood, updated_at
+        FROM entrytext
+        JOIN entries ON entrytext.rowid = entries.rowid
+        WHERE entrytext MATCH ?
+        ORDER BY timestamp DESC
+    "#;
+    let qry = search
+        .q
+        .filter(|q| !q.trim().is_empty())
+        .ok_or((StatusCode::BAD_REQUEST, "Missing search query".to_owned()))?;
+    site.check_query_length(&qry)?;
+    let entries = {
+        let cxn = lock_db(&cxn_arcmux, &site).await?;
+        let mut stmt = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let raw_entries = stmt
+            .query_map([&qry], RawEntry::from_row)
+            .map_err(convert_search_error)?;
+        let mut entries: Vec<Entry> = Vec::new();
+        for raw in raw_entries {
+            entries.push(raw.map_err(convert_search_error)?.try_into()?);
+        }
+        entries
+    };
+    if entries.is_empty() {
+        return Err((StatusCode::NOT_FOUND, format!("No entries match {:?}", qry)));
+    }
+
+    let markdown: Vec<String> = entries.iter().map(entry_markdown).collect();
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/markdown; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "inline; filename=\"search.md\"",
+            ),
+        ],
+        markdown.join("\n"),
+    )
+        .into_response())
+}
+
+#[derive(Template)]
+#[template(path = "day.html")]
+struct DayViewModel {
+    site: Site,
+    date: NaiveDate,
+    entries: Vec<Entry>,
+}
+
+impl Entry {
+    fn on_date(cxn: &mut rusqlite::Connection, date: NaiveDate) -> Result<Vec<Entry>, AppError> {
+        const QUERY: &str = r#"
+            SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+            FROM entries
+            WHERE date = ?
+            ORDER BY timestamp
+        "#;
+        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let mut entries = Vec::new();
+        let results = qry
+            .query_map([date.format("%Y-%m-%d").to_string()], RawEntry::from_row)
+            .map_err(convert_db_error)?;
+        for raw in results {
+            let raw = raw.map_err(convert_db_error)?;
+            entries.push(raw.try_into()?);
+        }
+        Ok(entries)
+    }
+}
+
+fn parse_date(date: &str) -> Result<NaiveDate, AppError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| {
+        (
+            StatusCode::BAD_REQUEST,
+            format!("Invalid date (expected YYYY-MM-DD): {}", date),
+        )
+    })
+}
+
+async fn get_day(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(date): Path<String>,
+) -> Response {
+    let date = parse_date(&date)?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entries = Entry::on_date(&mut cxn, date)?;
+    let vm = DayViewModel {
+        site,
+        date,
+        entries,
+    };
+    render(vm)
+}
+
+#[derive(Template)]
+#[template(path = "mood.html")]
+struct MoodViewModel {
+    site: Site,
+    mood: String,
+    entries: Vec<Entry>,
+}
+
+impl Entry {
+    fn with_mood(cxn: &mut rusqlite::Connection, mood: &str) -> Result<Vec<Entry>, AppError> {
+        const QUERY: &str = r#"
+            SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+            FROM entries
+            WHERE mood = ?
+            ORDER BY timestamp DESC
+        "#;
+        let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+        let mut entries = Vec::new();
+        let results = qry
+            .query_map([mood], RawEntry::from_row)
+            .map_err(convert_db_error)?;
+        for raw in results {
+            let raw = raw.map_err(convert_db_error)?;
+            entries.push(raw.try_into()?);
+        }
+        Ok(entries)
+    }
+}
+
+async fn get_mood(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    Path(mood): Path<String>,
+) -> Response {
+    if !MOODS.contains(&mood.as_str()) {
+        return Err((StatusCode::NOT_FOUND, format!("Unknown mood: {}", mood)));
+    }
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let entries = Entry::with_mood(&mut cxn, &mood)?;
+    let vm = MoodViewModel {
+        site,
+        mood,
+        entries,
+    };
+    render(vm)
+}
+
+#[derive(serde::Deserialize)]
+struct JumpToDate {
+    date: String,
+}
+
+/// Target of the index's "jump to date" form, which can only submit the date as a query.
+async fn get_day_query(
+    site: Site,
+    PageQuery(jump): PageQuery<JumpToDate>,
+) -> Result<Redirect, AppError> {
+    let date = parse_date(&jump.date)?;
+    Ok(Redirect::to(&site.url(&format!("/day/{}", date))))
+}
+
+#[derive(Template)]
+#[template(path = "search.html")]
+struct SearchViewModel {
+    site: Site,
+    query: String,
+    results: Vec<SearchResult>,
+    page: u32,
+    sort: SearchSort,
+    prev_url: Option<String>,
+    next_url: Option<String>,
+    /// Why the search couldn't be run, if it couldn't.
+    notice: Option<String>,
+    total: Option<SearchCount>,
+    /// Queries with misspelled words swapped for similar indexed terms, for searches that
+    /// found nothing.
+    suggestions: Vec<String>,
+}
+
+impl SearchViewModel {
+    fn search_url(&self, query: &str) -> String {
+        search_url(&self.site, query, 1, self.sort)
+    }
+
+    /// The first page of this search in another order.
+    fn sort_url(&self, sort: SearchSort) -> String {
+        search_url(&self.site, &self.query, 1, sort)
+    }
+
+    fn export_url(&self) -> String {
+        let params = form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", &self.query)
+            .finish();
+        self.site.url(&format!("/search/export.md?{}", params))
+    }
+}
+
+/// How many search results are shown per page.
+const SEARCH_PAGE_SIZE: u32 = 20;
+
+fn search_url(site: &Site, query: &str, page: u32, sort: SearchSort) -> String {
+    let mut params = form_urlencoded::Serializer::new(String::new());
+    params
+        .append_pair("q", query)
+        .append_pair("page", &page.to_string());
+    if sort == SearchSort::Relevance {
+        params.append_pair("sort", "relevance");
+    }
+    let params = params.finish();
+    site.url(&format!("/search?{}", params))
+}
+
+#[derive(serde::Serialize)]
+struct SearchResult {
+    entry_id: u32,
+    entry_timestamp: DateTime<Utc>,
+    entry_match: String,
+    /// `entry_match` as HTML, escaped, with the matching terms wrapped in `--snippet-start`
+    /// and `--snippet-end`.
+    #[serde(skip)]
+    highlighted_match: String,
+    /// A wider window of the entry around the first match.
+    excerpt: Option<String>,
+}
+
+/// Characters that `snippet` wraps matches in. They practically never appear in entries and
+/// survive escaping, so they can be turned into `<mark>` tags afterwards.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// How search result snippets are marked up.
+#[derive(Clone)]
+struct SnippetOptions {
+    /// HTML before each matching term. Comes from the command line, so it isn't escaped.
+    start: String,
+    /// HTML after each matching term.
+    end: String,
+    /// Plain text where the snippet cuts the entry off.
+    ellipsis: String,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        SnippetOptions {
+            start: String::from("<mark>"),
+            end: String::from("</mark>"),
+            ellipsis: String::from("..."),
+        }
+    }
+}
+
+/// Escape a snippet and highlight its matches.
+fn highlight_snippet(snippet: &str, options: &SnippetOptions) -> String {
+    escape_html(snippet)
+        .replace(MATCH_START, &options.start)
+        .replace(MATCH_END, &options.end)
+}
+
+/// Bytes of context to show on either side of the first match in an excerpt.
+const EXCERPT_CONTEXT: usize = 300;
+
+impl SearchResult {
+    fn fetch_excerpt(&mut self, cxn: &Connection, query: &str) -> Result<(), AppError> {
+        const BODY: &str = r#"
+            SELECT decrypt_body(body) FROM entries WHERE rowid = ?
+        "#;
+        let body: String = cxn
+            .query_row(BODY, [self.entry_id], |r| r.get(0))
+            .map_err(convert_db_error)?;
+        self.excerpt = Some(excerpt(&body, query));
+        Ok(())
+    }
+}
+
+/// Cut a window of `body` around the first word matching one of the query's terms, or from
+/// the start of the body if none of them can be found (e.g. a stemmed match).
+fn excerpt(body: &str, query: &str) -> String {
+    let start_of_match = first_match(body, query).unwrap_or(0);
+
+    let mut start = start_of_match.saturating_sub(EXCERPT_CONTEXT);
+    while !body.is_char_boundary(start) {
+        start -= 1;
+    }
+    if start > 0 {
+        start = body[start..]
+            .find(char::is_whitespace)
+            .map_or(start, |i| start + i);
+    }
+    let mut end = (start_of_match + EXCERPT_CONTEXT).min(body.len());
+    while !body.is_char_boundary(end) {
+        end += 1;
+    }
+    if end < body.len() {
+        end = body[..end]
+            .rfind(char::is_whitespace)
+            .unwrap_or(end)
+            .max(start);
+    }
+
+    let mut excerpt = String::new();
+    if start > 0 {
+        excerpt.push_str("...");
+    }
+    excerpt.push_str(body[start..end].trim());
+    if end < body.len() {
+        excerpt.push_str("...");
+    }
+    excerpt
+}
+
+/// Byte offset of the first word in `body` containing one of the query's terms.
+fn first_match(body: &str, query: &str) -> Option<usize> {
+    const OPERATORS: [&str; 4] = ["AND", "OR", "NOT", "NEAR"];
+    let terms: Vec<String> = query
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty() && !OPERATORS.contains(t))
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut word_start = None;
+    let boundary = std::iter::once((body.len(), ' '));
+    for (i, c) in body.char_indices().chain(boundary) {
+        if c.is_alphanumeric() {
+            word_start.get_or_insert(i);
+        } else if let Some(start) = word_start.take() {
+            let word = body[start..i].to_lowercase();
+            if terms.iter().any(|t| word.contains(t.as_str())) {
+                return Some(start);
+            }
+        }
+    }
+    None
+}
+
+impl RawSearchResult {
+    fn into_search_result(self, snippet: &SnippetOptions) -> Result<SearchResult, AppError> {
+        use chrono::NaiveDateTime;
+        let RawSearchResult {
+            entry_id,
+            entry_timestamp,
+            entry_match,
+        } = self;
+        let ndt = NaiveDateTime::from_timestamp_opt(entry_timestamp as i64, 0).ok_or((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Timestamp conversion errror".to_owned(),
+        ))?;
+        let entry_timestamp = DateTime::from_utc(ndt, Utc);
+        let result = SearchResult {
+            entry_id,
+            entry_timestamp,
+            highlighted_match: highlight_snippet(&entry_match, snippet),
+            entry_match: entry_match.replace([MATCH_START, MATCH_END], ""),
+            excerpt: None,
+        };
+        Ok(result)
+    }
+}
+
+struct RawSearchResult {
+    entry_id: u32,
+    entry_timestamp: u32,
+    entry_match: String,
+}
+
+impl TryFrom<&rusqlite::Row<'_>> for RawSearchResult {
+    type Error = rusqlite::Error;
+
+    fn try_from(row: &rusqlite::Row) -> Result<Self, Self::Error> {
+        let entry_id = row.get(0)?;
+        let entry_timestamp = row.get(1)?;
+        let entry_match = row.get(2)?;
+
+        let result = RawSearchResult {
+            entry_id,
+            entry_timestamp,
+            entry_match,
+        };
+        Ok(result)
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct SearchQuery {
+    q: Option<String>,
+    /// 1-based page of results to show.
+    page: Option<u32>,
+    #[serde(default)]
+    sort: SearchSort,
+}
+
+/// The order search results are listed in.
+#[derive(Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SearchSort {
+    #[default]
+    Newest,
+    /// Best match first, by FTS5's BM25 `rank`.
+    Relevance,
+}
+
+impl SearchSort {
+    fn order_by(self) -> &'static str {
+        match self {
+            SearchSort::Newest => "ORDER BY entries.timestamp DESC",
+            SearchSort::Relevance => "ORDER BY rank, entries.timestamp DESC",
+        }
+    }
+}
+
+async fn get_search(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageQuery(search): PageQuery<SearchQuery>,
+) -> Result<axum::response::Response, AppError> {
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    search_page(&cxn, site, search)
+}
+
+/// Search with the query in the request body, for queries too long (or too private) to put
+/// in a URL.
+async fn post_search(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    PageForm(search): PageForm<SearchQuery>,
+) -> Result<axum::response::Response, AppError> {
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    search_page(&cxn, site, search)
+}
+
+/// Render a page of search results, with `Link` headers pointing to this page and its
+/// neighbours so clients can page through results without parsing the HTML.
+fn search_page(
+    cxn: &Connection,
+    site: Site,
+    search: SearchQuery,
+) -> Result<axum::response::Response, AppError> {
+    use axum::http::{header::LINK, HeaderValue};
+    use axum::response::IntoResponse;
+
+    let qry = search.q;
+    let page = search.page.unwrap_or(1).max(1);
+    let sort = search.sort;
+    debug!("Search for: {:?} (page {})", qry, page);
+    let mut status = StatusCode::OK;
+    let mut notice = None;
+    let mut total = None;
+    let mut suggestions = Vec::new();
+    let (results, has_more) = match &qry {
+        Some(qry) => match site.check_query_length(qry) {
+            Ok(()) => {
+                total = Some(count_search_results(cxn, qry, site.max_search_results)?);
+                let (results, has_more) = search_results(cxn, qry, page, sort, &site)?;
+                if results.is_empty() && page == 1 {
+                    suggestions = suggest_queries(cxn, qry)?;
+                }
+                (results, has_more)
+            }
+            Err((error_status, message)) => {
+                status = error_status;
+                notice = Some(message);
+                (Vec::new(), false)
+            }
+        },
+        None => (Vec::new(), false),
+    };
+    dbg!("Found {} results", results.len());
+
+    let query = qry.unwrap_or_default();
+    let prev_url = (page > 1).then(|| search_url(&site, &query, page - 1, sort));
+    let next_url = has_more.then(|| search_url(&site, &query, page + 1, sort));
+    let mut links = vec![format!(
+        "<{}>; rel=\"canonical\"",
+        search_url(&site, &query, page, sort)
+    )];
+    if let Some(url) = &prev_url {
+        links.push(format!("<{}>; rel=\"prev\"", url));
+    }
+    if let Some(url) = &next_url {
+        links.push(format!("<{}>; rel=\"next\"", url));
+    }
+
+    let vm = SearchViewModel {
+        site,
+        results,
+        query,
+        page,
+        sort,
+        prev_url,
+        next_url,
+        notice,
+        total,
+        suggestions,
+    };
+    let mut response = (status, render(vm)?).into_response();
+    if let Ok(links) = HeaderValue::from_str(&links.join(", ")) {
+        response.headers_mut().insert(LINK, links);
+    }
+    Ok(response)
+}
+
+/// How many alternative queries to suggest for a search that found nothing.
+const MAX_SUGGESTIONS: usize = 3;
+
+/// Queries like `qry` with each word that isn't in the search index replaced by the indexed
+/// terms closest to it by edit distance, more common terms first.
+fn suggest_queries(cxn: &Connection, qry: &str) -> Result<Vec<String>, AppError> {
+    const HAS_VOCABULARY: &str = r#"
+        SELECT COUNT(*) FROM temp.sqlite_master WHERE name = 'entrytext_vocab'
+    "#;
+    const EXISTS: &str = r#"
+        SELECT COUNT(*) FROM temp.entrytext_vocab WHERE term = ?
+    "#;
+    const CANDIDATES: &str = r#"
+        SELECT term, doc FROM temp.entrytext_vocab WHERE length(term) BETWEEN ? AND ?
+    "#;
+    let has_vocabulary: u32 = cxn
+        .query_row(HAS_VOCABULARY, [], |r| r.get(0))
+        .map_err(convert_db_error)?;
+    if has_vocabulary == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Split the query into words and everything around them, so FTS syntax is kept.
+    let mut pieces: Vec<(String, Vec<String>)> = Vec::new();
+    let mut rest = qry;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| c.is_alphanumeric() != rest.starts_with(char::is_alphanumeric))
+            .unwrap_or(rest.len());
+        let (piece, tail) = rest.split_at(split);
+        pieces.push((piece.to_owned(), Vec::new()));
+        rest = tail;
+    }
+    let mut misspelled = false;
+    for i in 0..pieces.len() {
+        let word = pieces[i].0.to_lowercase();
+        let is_operator = ["AND", "OR", "NOT", "NEAR"].contains(&pieces[i].0.as_str());
+        let is_column = pieces.get(i + 1).is_some_and(|(p, _)| p.starts_with(':'));
+        if !word.starts_with(char::is_alphanumeric) || is_operator || is_column {
+            continue;
+        }
+        let found: u32 = cxn
+            .query_row(EXISTS, [&word], |r| r.get(0))
+            .map_err(convert_db_error)?;
+        if found > 0 {
+            continue;
+        }
+        let length = word.chars().count();
+        let max_distance = if length <= 4 { 1 } else { 2 };
+        let mut stmt = cxn.prepare(CANDIDATES).map_err(convert_db_error)?;
+        let rows = stmt
+            .query_map(
+                rusqlite::params![length.saturating_sub(max_distance), length + max_distance],
+                |r| Ok((r.get::<_, String>(0)?, r.get::<_, u32>(1)?)),
+            )
+            .map_err(convert_db_error)?;
+        let mut candidates = Vec::new();
+        for row in rows {
+            let (term, docs) = row.map_err(convert_db_error)?;
+            let distance = edit_distance(&word, &term);
+            if distance <= max_distance {
+                candidates.push((distance, std::cmp::Reverse(docs), term));
+            }
+        }
+        candidates.sort();
+        pieces[i].1 = candidates
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, _, term)| term)
+            .collect();
+        misspelled |= !pieces[i].1.is_empty();
+    }
+    if !misspelled {
+        return Ok(Vec::new());
+    }
+
+    // The nth suggestion uses each word's nth closest term, or its last if it has fewer.
+    let mut suggestions: Vec<String> = Vec::new();
+    for n in 0..MAX_SUGGESTIONS {
+        let suggestion: String = pieces
+            .iter()
+            .map(|(piece, terms)| match terms.get(n).or(terms.last()) {
+                Some(term) => term.as_str(),
+                None => piece.as_str(),
+            })
+            .collect();
+        if !suggestions.contains(&suggestion) {
+            suggestions.push(suggestion);
+        }
+    }
+    Ok(suggestions)
+}
+
+/// Levenshtein distance between two strings, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Errors running a search are almost always FTS5 rejecting the query, e.g. `foo:bar` names
+/// a column that doesn't exist. Only some of those messages say they came from FTS5.
+fn convert_search_error(err: rusqlite::Error) -> AppError {
+    match err {
+        rusqlite::Error::SqliteFailure(e, Some(msg)) if e.code == rusqlite::ErrorCode::Unknown => {
+            warn!("Invalid search query: {}", msg);
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid search query ({})", msg),
+            )
+        }
+        err => convert_db_error(err),
+    }
+}
+
+/// How many entries match a search, counting no further than the configured maximum.
+struct SearchCount {
+    total: u32,
+    /// Whether there were more matches than were counted.
+    capped: bool,
+}
+
+impl std::fmt::Display for SearchCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let digits = self.total.to_string();
+        let mut total = String::new();
+        for (i, c) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i).is_multiple_of(3) {
+                total.push(',');
+            }
+            total.push(c);
+        }
+        let plus = if self.capped { "+" } else { "" };
+        let noun = if self.total == 1 && !self.capped {
+            "result"
+        } else {
+            "results"
+        };
+        write!(f, "{}{} {}", total, plus, noun)
+    }
+}
+
+/// Count the matches for `qry`, stopping after `max_results` so common words don't make
+/// SQLite walk the whole index.
+fn count_search_results(
+    cxn: &Connection,
+    qry: &str,
+    max_results: u32,
+) -> Result<SearchCount, AppError> {
+    const QUERY: &str = r#"
+        SELECT COUNT(*) FROM (SELECT 1 FROM entrytext WHERE entrytext MATCH ? LIMIT ?)
+    "#;
+    let count: u32 = cxn
+        .query_row(
+            QUERY,
+            rusqlite::params![qry, max_results.saturating_add(1)],
+            |r| r.get(0),
+        )
+        .map_err(convert_search_error)?;
+    Ok(SearchCount {
+        total: count.min(max_results),
+        capped: count > max_results,
+    })
+}
+
+/// One page of results for `qry`, and whether there are more after it. Results past
+/// `--max-search-results` aren't paged through.
+fn search_results(
+    cxn: &Connection,
+    qry: &str,
+    page: u32,
+    sort: SearchSort,
+    site: &SiteConfig,
+) -> Result<(Vec<SearchResult>, bool), AppError> {
+    let query = format!(
+        r#"
+        SELECT entries.rowid, entries.timestamp, snippet(entrytext, 0, char(2), char(3), ?, 32)
+        FROM entrytext
+        JOIN entries ON entrytext.rowid = entries.rowid
+        WHERE entrytext MATCH ?
+        {}
+        LIMIT ? OFFSET ?
+        "#,
+        sort.order_by()
+    );
+    // Fetch one extra result to find out whether there's a next page.
+    let max_results = site.max_search_results;
+    let limit = SEARCH_PAGE_SIZE + 1;
+    let offset = (page - 1).saturating_mul(SEARCH_PAGE_SIZE);
+    if offset >= max_results {
+        return Ok((Vec::new(), false));
+    }
+    let mut stmt = cxn.prepare(&query).map_err(convert_db_error)?;
+    let raw_results = stmt
+        .query_map(
+            rusqlite::params![site.snippet.ellipsis, qry, limit, offset],
+            |r| r.try_into(),
+        )
+        .map_err(convert_search_error)?;
+    let mut results: Vec<SearchResult> = Vec::new();
+    for raw in raw_results {
+        let result: RawSearchResult = raw.map_err(convert_search_error)?;
+        results.push(result.into_search_result(&site.snippet)?);
+    }
+    let has_more = results.len() > SEARCH_PAGE_SIZE as usize
+        && offset.saturating_add(SEARCH_PAGE_SIZE) < max_results;
+    results.truncate(SEARCH_PAGE_SIZE.min(max_results - offset) as usize);
+    for result in results.iter_mut() {
+        result.fetch_excerpt(cxn, qry)?;
+    }
+    Ok((results, has_more))
+}
+
+#[derive(serde::Serialize)]
+struct ApiSearchResults {
+    results: Vec<SearchResult>,
+    /// Results on this page.
+    count: usize,
+    /// Matching entries overall, up to `--max-search-results`.
+    total: u32,
+    /// Whether `total` stopped at the maximum and there are more matches.
+    total_capped: bool,
+    page: u32,
+    next_page: Option<u32>,
+}
+
+/// Search for scripts. Unlike the search page, a missing query is an error, so clients can
+/// tell "no query" apart from "no matches".
+async fn get_api_search(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(search): ApiQuery<SearchQuery>,
+) -> Result<Json<ApiSearchResults>, ApiError> {
+    let qry = search
+        .q
+        .filter(|q| !q.trim().is_empty())
+        .ok_or((StatusCode::BAD_REQUEST, String::from("missing q")))?;
+    site.check_query_length(&qry)?;
+    let page = search.page.unwrap_or(1).max(1);
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let total = count_search_results(&cxn, &qry, site.max_search_results)?;
+    let (results, has_more) = search_results(&cxn, &qry, page, search.sort, &site)?;
+    Ok(Json(ApiSearchResults {
+        count: results.len(),
+        total: total.total,
+        total_capped: total.capped,
+        results,
+        page,
+        next_page: has_more.then_some(page + 1),
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct CountQuery {
+    q: Option<String>,
+    mood: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct ApiCount {
+    count: u32,
+    /// Whether a search's `count` stopped at `--max-search-results`.
+    capped: bool,
+}
+
+/// How many entries match a search or have a mood, without fetching them, e.g. for
+/// dashboard badges.
+async fn get_api_count(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(query): ApiQuery<CountQuery>,
+) -> Result<Json<ApiCount>, ApiError> {
+    const MOOD_QUERY: &str = "SELECT COUNT(*) FROM entries WHERE mood = ?";
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    match (query.q.filter(|q| !q.trim().is_empty()), query.mood) {
+        (Some(qry), None) => {
+            site.check_query_length(&qry)?;
+            let total = count_search_results(&cxn, &qry, site.max_search_results)?;
+            Ok(Json(ApiCount {
+                count: total.total,
+                capped: total.capped,
+            }))
+        }
+        (None, Some(mood)) => {
+            if !MOODS.contains(&mood.as_str()) {
+                return Err((StatusCode::BAD_REQUEST, format!("Unknown mood: {}", mood)).into());
+            }
+            let count = cxn
+                .query_row(MOOD_QUERY, [&mood], |r| r.get(0))
+                .map_err(convert_db_error)?;
+            Ok(Json(ApiCount {
+                count,
+                capped: false,
+            }))
+        }
+        _ => Err((
+            StatusCode::BAD_REQUEST,
+            String::from("give one of q or mood"),
+        )
+            .into()),
+    }
+}
+
+#[derive(serde::Serialize)]
+struct Suggestion {
+    id: u32,
+    date: String,
+    /// The start of the entry's first line.
+    title: String,
+    url: String,
+}
+
+/// How many entries `/api/suggest` returns.
+const SUGGESTIONS_LIMIT: u32 = 5;
+
+/// A few entries matching what's been typed so far, for the search box's autocomplete. The
+/// last word is matched as a prefix since it's probably still being typed. Only the start
+/// of each entry is read, and it comes from the search index so nothing is decrypted.
+async fn get_api_suggest(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(search): ApiQuery<SearchQuery>,
+) -> Result<Json<Vec<Suggestion>>, ApiError> {
+    const QUERY: &str = r#"
+        SELECT entries.rowid, entries.date, substr(entrytext.body, 1, 200)
+        FROM entrytext
+        JOIN entries ON entrytext.rowid = entries.rowid
+        WHERE entrytext MATCH ?
+        ORDER BY rank
+        LIMIT ?
+    "#;
+    let qry = search.q.unwrap_or_default();
+    site.check_query_length(&qry)?;
+    // Quote each word so half-typed FTS syntax can't make the query invalid.
+    let words: Vec<String> = qry
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| format!("\"{}\"", w))
+        .collect();
+    if words.is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+    let fts_query = format!("{}*", words.join(" "));
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let mut stmt = cxn.prepare(QUERY).map_err(convert_db_error)?;
+    let rows = stmt
+        .query_map(rusqlite::params![fts_query, SUGGESTIONS_LIMIT], |r| {
+            Ok((r.get(0)?, r.get(1)?, r.get::<_, String>(2)?))
+        })
+        .map_err(convert_search_error)?;
+    let mut suggestions = Vec::new();
+    for row in rows {
+        let (id, date, start) = row.map_err(convert_search_error)?;
+        let first_line = start.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+        let title = first_line
+            .trim_start_matches(|c: char| "#>-* ".contains(c))
+            .chars()
+            .take(80)
+            .collect();
+        suggestions.push(Suggestion {
+            id,
+            date,
+            title,
+            url: site.url(&format!("/entry/{}", id)),
+        });
+    }
+    Ok(Json(suggestions))
+}
+
+#[derive(serde::Deserialize)]
+struct Draft {
+    body: String,
+    /// The tab's draft session; see `post_draft`.
+    #[serde(default)]
+    session: String,
+    /// Set on the editor's first save when it was given the sessionless draft, which it
+    /// then takes over.
+    #[serde(default)]
+    adopt: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct DraftSession {
+    #[serde(default)]
+    session: String,
+    /// Also discard the sessionless draft the editor was given; see `Draft::adopt`.
+    #[serde(default)]
+    adopt: bool,
+}
+
+#[derive(serde::Deserialize)]
+struct ChangesQuery {
+    since: i64,
+    /// Breaks ties between entries written in the same second; see `get_changes`.
+    after_id: Option<u32>,
+}
+
+#[derive(serde::Serialize)]
+struct ChangesCursor {
+    since: i64,
+    after_id: u32,
+}
+
+#[derive(serde::Serialize)]
+struct DeletedEntry {
+    id: u32,
+    deleted_at: i64,
+}
+
+#[derive(serde::Serialize)]
+struct Changes {
+    entries: Vec<Entry>,
+    /// Entries deleted in the same span of time as `entries`.
+    deleted: Vec<DeletedEntry>,
+    /// Where to continue from, if there are more changes than fit in one response.
+    next: Option<ChangesCursor>,
+}
+
+const CHANGES_PAGE_SIZE: usize = 100;
+
+/// Entries created or edited after `since` (a Unix timestamp), oldest change first, for
+/// clients that mirror the diary.
+///
+/// At most `CHANGES_PAGE_SIZE` entries are returned at once. When there are more, `next`
+/// holds the `since` and `after_id` to request the following page with. Several entries
+/// can share an `updated_at`, so a page can end partway through them; `after_id` picks up
+/// from the last one returned. Once `next` is null the client is up to date and can poll
+/// again later with `since` set to the newest `updated_at` or `deleted_at` it has seen.
+///
+/// Deletions are reported from `since` up to the end of the page's entries, so a deletion
+/// can be reported twice; deleting again is harmless. Tombstones for ids a newer entry
+/// has reused are left out, since that entry replaces the deleted one.
+async fn get_changes(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(query): ApiQuery<ChangesQuery>,
+) -> Result<Json<Changes>, ApiError> {
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    const QUERY: &str = r#"
+        SELECT rowid, date, timestamp, decrypt_body(body), mood, updated_at
+        FROM entries
+        WHERE updated_at > $1 OR (updated_at = $1 AND rowid > $2)
+        ORDER BY updated_at, rowid
+        LIMIT $3
+    "#;
+    let mut qry = cxn.prepare(QUERY).map_err(convert_db_error)?;
+    let results = qry
+        .query_map(
+            rusqlite::params![
+                query.since,
+                query.after_id.unwrap_or(u32::MAX),
+                CHANGES_PAGE_SIZE + 1
+            ],
+            RawEntry::from_row,
+        )
+        .map_err(convert_db_error)?;
+    let mut entries: Vec<Entry> = Vec::new();
+    for raw in results {
+        let raw = raw.map_err(convert_db_error)?;
+        entries.push(raw.try_into()?);
+    }
+    let next = if entries.len() > CHANGES_PAGE_SIZE {
+        entries.truncate(CHANGES_PAGE_SIZE);
+        entries.last().map(|last| ChangesCursor {
+            since: last.updated_at,
+            after_id: last.id,
+        })
+    } else {
+        None
+    };
+
+    const DELETED: &str = r#"
+        SELECT entry_id, MAX(deleted_at)
+        FROM deleted_entries
+        WHERE deleted_at >= $1 AND deleted_at <= $2
+            AND NOT EXISTS (SELECT 1 FROM entries WHERE entries.rowid = entry_id)
+        GROUP BY entry_id
+        ORDER BY 2, 1
+    "#;
+    let until = next.as_ref().map_or(i64::MAX, |next| next.since);
+    let mut qry = cxn.prepare(DELETED).map_err(convert_db_error)?;
+    let deleted = qry
+        .query_map([query.since, until], |r| {
+            Ok(DeletedEntry {
+                id: r.get(0)?,
+                deleted_at: r.get(1)?,
+            })
+        })
+        .map_err(convert_db_error)?
+        .collect::<Result<_, _>>()
+        .map_err(convert_db_error)?;
+    Ok(Json(Changes {
+        entries,
+        deleted,
+        next,
+    }))
+}
+
+#[derive(serde::Serialize)]
+struct YearCount {
+    year: u32,
+    count: u32,
+}
+
+/// The years the diary has entries in and how many each has, as listed on the index.
+async fn get_api_years(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+) -> Result<Json<Vec<YearCount>>, ApiError> {
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    let years = year_counts(&mut cxn)?
+        .into_iter()
+        .map(|(year, count)| YearCount { year, count })
+        .collect();
+    Ok(Json(years))
+}
+
+/// The year page's data: each month's first entries and how many it has in all.
+async fn get_api_year(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiPath(year): ApiPath<u32>,
+) -> Result<Json<YearViewModel>, ApiError> {
+    check_year(year)?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    check_year_has_entries(&cxn, year)?;
+    Ok(Json(YearViewModel::get(&mut cxn, site, year)?))
+}
+
+/// An OpenAPI 3 description of the JSON API and the plain-text entry routes, for generating
+/// clients. Written by hand, so changes to those handlers need to be made here too.
+async fn get_openapi(site: Site) -> Json<serde_json::Value> {
+    use serde_json::json;
+
+    let error = json!({"$ref": "#/components/responses/Error"});
+    let entry_id = json!({
+        "name": "rowid", "in": "path", "required": true,
+        "schema": {"type": "integer", "minimum": 1},
+    });
+    let tz_offset = json!({
+        "name": "tz_offset", "in": "query",
+        "description": "Offset from UTC in minutes; defaults to the server's timezone.",
+        "schema": {"type": "integer"},
+    });
+    let server = if site.prefix.is_empty() {
+        "/"
+    } else {
+        &site.prefix
+    };
+    Json(json!({
+        "openapi": "3.0.3",
+        "info": {"title": site.title, "version": env!("CARGO_PKG_VERSION")},
+        "servers": [{"url": server}],
+        "paths": {
+            "/api/search": {"get": {
+                "summary": "Search entries, newest or best match first",
+                "parameters": [
+                    {"name": "q", "in": "query", "required": true,
+                     "description": "An FTS5 query.", "schema": {"type": "string"}},
+                    {"name": "page", "in": "query",
+                     "schema": {"type": "integer", "minimum": 1, "default": 1}},
+                    {"name": "sort", "in": "query",
+                     "schema": {"type": "string", "enum": ["newest", "relevance"], "default": "newest"}},
+                ],
+                "responses": {
+                    "200": {"description": "A page of results", "content": {"application/json":
+                        {"schema": {"$ref": "#/components/schemas/SearchResults"}}}},
+                    "400": error,
+                },
+            }},
+            "/api/suggest": {"get": {
+                "summary": "A few entries matching a partly typed search, best match first",
+                "parameters": [{"name": "q", "in": "query", "schema": {"type": "string"}}],
+                "responses": {
+                    "200": {"description": "Up to five entries", "content": {"application/json":
+                        {"schema": {"type": "array", "items": {
+                            "type": "object",
+                            "required": ["id", "date", "title", "url"],
+                            "properties": {
+                                "id": {"type": "integer"},
+                                "date": {"type": "string", "format": "date"},
+                                "title": {"type": "string"},
+                                "url": {"type": "string"},
+                            },
+                        }}}}},
+                    "400": error,
+                },
+            }},
+            "/api/count": {"get": {
+                "summary": "How many entries match a search or have a mood",
+                "description": "Give exactly one of q or mood.",
+                "parameters": [
+                    {"name": "q", "in": "query",
+                     "description": "An FTS5 query.", "schema": {"type": "string"}},
+                    {"name": "mood", "in": "query",
+                     "schema": {"$ref": "#/components/schemas/Mood"}},
+                ],
+                "responses": {
+                    "200": {"description": "The count", "content": {"application/json":
+                        {"schema": {
+                            "type": "object",
+                            "required": ["count", "capped"],
+                            "properties": {
+                                "count": {"type": "integer"},
+                                "capped": {"type": "boolean",
+                                    "description": "Whether a search's count stopped at the server's maximum."},
+                            },
+                        }}}},
+                    "400": error,
+                },
+            }},
+            "/api/entry": {"post": {
+                "summary": "Create an entry",
+                "parameters": [{
+                    "name": "Idempotency-Key", "in": "header",
+                    "description": "Retrying with the same key returns the entry the first request created.",
+                    "schema": {"type": "string"},
+                }],
+                "requestBody": {"required": true, "content": {"application/json": {"schema": {
+                    "type": "object",
+                    "required": ["body"],
+                    "properties": {
+                        "body": {"type": "string", "description": "Markdown."},
+                        "mood": {"$ref": "#/components/schemas/Mood"},
+                    },
+                }}}},
+                "responses": {
+                    "201": {"description": "Created", "content": {"application/json":
+                        {"schema": {"$ref": "#/components/schemas/CreatedEntry"}}}},
+                    "200": {"description": "Already created with this Idempotency-Key",
+                        "content": {"application/json":
+                            {"schema": {"$ref": "#/components/schemas/CreatedEntry"}}}},
+                    "400": error,
+                    "503": error,
+                },
+            }},
+            "/api/changes": {"get": {
+                "summary": "Entries created, edited or deleted since a point in time, oldest change first",
+                "parameters": [
+                    {"name": "since", "in": "query", "required": true,
+                     "description": "Unix timestamp.", "schema": {"type": "integer"}},
+                    {"name": "after_id", "in": "query",
+                     "description": "From the previous response's `next` cursor.",
+                     "schema": {"type": "integer"}},
+                ],
+                "responses": {
+                    "200": {"description": "Changed entries", "content": {"application/json":
+                        {"schema": {"$ref": "#/components/schemas/Changes"}}}},
+                    "400": error,
+                },
+            }},
+            "/api/activity-by-hour": {"get": {
+                "summary": "Entries written in each hour of the day",
+                "parameters": [tz_offset],
+                "responses": {
+                    "200": {"description": "All 24 hours", "content": {"application/json":
+                        {"schema": {"type": "array", "items": {
+                            "type": "object",
+                            "required": ["hour", "entries"],
+                            "properties": {
+                                "hour": {"type": "integer", "minimum": 0, "maximum": 23},
+                                "entries": {"type": "integer"},
+                            },
+                        }}}}},
+                    "400": error,
+                },
+            }},
+            "/api/years": {"get": {
+                "summary": "Years with entries and how many each has",
+                "responses": {
+                    "200": {"description": "Years, newest first", "content": {"application/json":
+                        {"schema": {"type": "array", "items": {
+                            "type": "object",
+                            "required": ["year", "count"],
+                            "properties": {
+                                "year": {"type": "integer"},
+                                "count": {"type": "integer"},
+                            },
+                        }}}}},
+                },
+            }},
+            "/api/year/{year}": {"get": {
+                "summary": "A year's entries grouped by month, as on the year page",
+                "parameters": [{"name": "year", "in": "path", "required": true,
+                    "schema": {"type": "integer", "minimum": YEARS.start(), "maximum": YEARS.end()}}],
+                "responses": {
+                    "200": {"description": "Months with entries, in order", "content": {"application/json":
+                        {"schema": {
+                            "type": "object",
+                            "required": ["year", "months", "entry_count", "word_count"],
+                            "properties": {
+                                "year": {"type": "integer"},
+                                "months": {"type": "array", "items": {
+                                    "type": "object",
+                                    "required": ["month", "entries", "total"],
+                                    "properties": {
+                                        "month": {"type": "string", "example": "January"},
+                                        "entries": {"type": "array", "description":
+                                            "The month's first entries, up to --entries-per-month",
+                                            "items": {"$ref": "#/components/schemas/Entry"}},
+                                        "total": {"type": "integer"},
+                                    },
+                                }},
+                                "entry_count": {"type": "integer"},
+                                "word_count": {"type": "integer"},
+                            },
+                        }}}},
+                    "400": error,
+                    "404": {"$ref": "#/components/responses/Error"},
+                },
+            }},
+            "/entry/{rowid}": {"get": {
+                "summary": "An entry, in the format the Accept header asks for",
+                "parameters": [entry_id, tz_offset],
+                "responses": {
+                    "200": {"description": "The entry", "content": {
+                        "text/html": {"schema": {"type": "string"}},
+                        "application/json": {"schema": {"$ref": "#/components/schemas/Entry"}},
+                        "text/markdown": {"schema": {"type": "string"}},
+                    }},
+                    "404": {"description": "No such entry"},
+                },
+            }},
+            "/entry/{rowid}/raw": {"get": {
+                "summary": "An entry's markdown",
+                "parameters": [entry_id],
+                "responses": {
+                    "200": {"description": "The entry body",
+                        "content": {"text/plain": {"schema": {"type": "string"}}}},
+                    "404": {"description": "No such entry"},
+                },
+            }},
+            "/entry/{rowid}/plain": {"get": {
+                "summary": "An entry's text with the markdown removed",
+                "parameters": [entry_id],
+                "responses": {
+                    "200": {"description": "The entry text",
+                        "content": {"text/plain": {"schema": {"type": "string"}}}},
+                    "404": {"description": "No such entry"},
+                },
+            }},
+        },
+        "components": {
+            "schemas": {
+                "Mood": {"type": "string", "nullable": true, "enum": MOODS},
+                "Entry": {
+                    "type": "object",
+                    "required": ["id", "date", "timestamp", "body", "mood", "updated_at"],
+                    "properties": {
+                        "id": {"type": "integer"},
+                        "date": {"type": "string", "format": "date",
+                            "description": "The server's local date when it was written."},
+                        "timestamp": {"type": "string", "format": "date-time"},
+                        "body": {"type": "string"},
+                        "mood": {"$ref": "#/components/schemas/Mood"},
+                        "updated_at": {"type": "integer", "description": "Unix timestamp."},
+                    },
+                },
+                "SearchResults": {
+                    "type": "object",
+                    "required": ["results", "count", "total", "total_capped", "page", "next_page"],
+                    "properties": {
+                        "results": {"type": "array", "items": {
+                            "type": "object",
+                            "required": ["entry_id", "entry_timestamp", "entry_match", "excerpt"],
+                            "properties": {
+                                "entry_id": {"type": "integer"},
+                                "entry_timestamp": {"type": "string", "format": "date-time"},
+                                "entry_match": {"type": "string"},
+                                "excerpt": {"type": "string", "nullable": true},
+                            },
+                        }},
+                        "count": {"type": "integer", "description": "Results on this page."},
+                        "total": {"type": "integer"},
+                        "total_capped": {"type": "boolean",
+                            "description": "Whether there are more matches than `total`."},
+                        "page": {"type": "integer"},
+                        "next_page": {"type": "integer", "nullable": true},
+                    },
+                },
+                "CreatedEntry": {
+                    "type": "object",
+                    "required": ["id", "url"],
+                    "properties": {
+                        "id": {"type": "integer"},
+                        "url": {"type": "string"},
+                    },
+                },
+                "Changes": {
+                    "type": "object",
+                    "required": ["entries", "deleted", "next"],
+                    "properties": {
+                        "entries": {"type": "array",
+                            "items": {"$ref": "#/components/schemas/Entry"}},
+                        "deleted": {"type": "array", "items": {
+                            "type": "object",
+                            "required": ["id", "deleted_at"],
+                            "properties": {
+                                "id": {"type": "integer"},
+                                "deleted_at": {"type": "integer", "description": "Unix timestamp."},
+                            },
+                        }},
+                        "next": {
+                            "type": "object",
+                            "nullable": true,
+                            "required": ["since", "after_id"],
+                            "properties": {
+                                "since": {"type": "integer"},
+                                "after_id": {"type": "integer"},
+                            },
+                        },
+                    },
+                },
+            },
+            "responses": {
+                "Error": {"description": "The request failed", "content": {"application/json":
+                    {"schema": {
+                        "type": "object",
+                        "required": ["error", "status"],
+                        "properties": {
+                            "error": {"type": "string"},
+                            "status": {"type": "integer"},
+                        },
+                    }}}},
+            },
+        },
+    }))
+}
+
+#[derive(serde::Deserialize)]
+struct ActivityQuery {
+    /// Offset from UTC in minutes to count hours in, like `EntryQuery::tz_offset`. Defaults
+    /// to the server's timezone.
+    tz_offset: Option<i32>,
+}
+
+#[derive(serde::Serialize)]
+struct HourActivity {
+    hour: u32,
+    entries: u32,
+}
+
+/// How many entries were written in each hour of the day, for charting when the diary gets
+/// written. Every hour from 0 to 23 is included, even if it has no entries.
+async fn get_activity_by_hour(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(query): ApiQuery<ActivityQuery>,
+) -> Result<Json<Vec<HourActivity>>, ApiError> {
+    const LOCAL: &str = r#"
+        SELECT CAST(strftime('%H', timestamp + ?, 'unixepoch', 'localtime') AS INTEGER), COUNT(*)
+        FROM entries
+        GROUP BY 1
+    "#;
+    const OFFSET: &str = r#"
+        SELECT CAST(strftime('%H', timestamp + ?, 'unixepoch') AS INTEGER), COUNT(*)
+        FROM entries
+        GROUP BY 1
+    "#;
+    let (sql, offset_secs) = match query.tz_offset {
+        Some(minutes) => (OFFSET, parse_tz_offset(minutes)?.local_minus_utc()),
+        None => (LOCAL, 0),
+    };
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let mut stmt = cxn.prepare(sql).map_err(convert_db_error)?;
+    let rows = stmt
+        .query_map([offset_secs], |r| Ok((r.get(0)?, r.get(1)?)))
+        .map_err(convert_db_error)?;
+    let mut hours: Vec<HourActivity> = (0..24)
+        .map(|hour| HourActivity { hour, entries: 0 })
+        .collect();
+    for row in rows {
+        let (hour, entries): (usize, u32) = row.map_err(convert_db_error)?;
+        if let Some(activity) = hours.get_mut(hour) {
+            activity.entries = entries;
+        }
+    }
+    Ok(Json(hours))
+}
+
+/// When the draft was last saved, so the editor can show the user that autosave is working.
+#[derive(serde::Serialize)]
+struct DraftStatus {
+    saved_at: Option<DateTime<Utc>>,
+}
+
+/// Save a session's draft. Each tab's editor has its own session, so tabs never overwrite
+/// each other's drafts.
+async fn post_draft(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiForm(draft): ApiForm<Draft>,
+) -> Result<Json<DraftStatus>, ApiError> {
+    site.check_writable()?;
+    let mut cxn = lock_db(&cxn_arcmux, &site).await?;
+    const SAVE: &str = r#"
+        INSERT INTO draft (draft, saved_at, session) VALUES (encrypt_body($1), unixepoch('now'), $2)
+        ON CONFLICT (session) DO UPDATE SET draft = excluded.draft, saved_at = excluded.saved_at
+        RETURNING saved_at
+    "#;
+    let tx = cxn.transaction().map_err(convert_db_error)?;
+    if draft.adopt && !draft.session.is_empty() {
+        clear_draft(&tx, "")?;
+    }
+    let saved_at: i64 = tx
+        .query_row(SAVE, rusqlite::params![&draft.body, &draft.session], |r| {
+            r.get(0)
+        })
+        .map_err(convert_db_error)?;
+    tx.commit().map_err(convert_db_error)?;
+    Ok(Json(DraftStatus {
+        saved_at: Some(convert_timestamp(saved_at)?),
+    }))
+}
+
+/// A session's draft, for the editor to restore after a reload.
+#[derive(serde::Serialize)]
+struct SessionDraft {
+    body: Option<String>,
+    saved_at: Option<DateTime<Utc>>,
+    /// Drafts saved by other tabs' sessions.
+    other_drafts: u32,
+}
+
+async fn get_draft_status(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(query): ApiQuery<DraftSession>,
+) -> Result<Json<SessionDraft>, ApiError> {
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    let draft = get_draft(&cxn, &query.session)?;
+    Ok(Json(SessionDraft {
+        saved_at: draft.as_ref().and_then(|draft| draft.saved_at),
+        body: draft.map(|draft| draft.body),
+        other_drafts: count_drafts(&cxn, &query.session)?,
+    }))
+}
+
+fn clear_draft(cxn: &Connection, session: &str) -> Result<(), AppError> {
+    const DELETE: &str = r#"
+        DELETE FROM draft WHERE session = $1
+    "#;
+    cxn.execute(DELETE, [session]).map_err(convert_db_error)?;
+    Ok(())
+}
+
+/// How many sessions other than `session` have drafts, not counting the sessionless one.
+fn count_drafts(cxn: &Connection, session: &str) -> Result<u32, AppError> {
+    const COUNT: &str = r#"
+        SELECT COUNT(*) FROM draft WHERE session NOT IN ($1, '')
+    "#;
+    cxn.query_row(COUNT, [session], |r| r.get(0))
+        .map_err(convert_db_error)
+}
+
+#[derive(Default)]
+struct SavedDraft {
+    body: String,
+    saved_at: Option<DateTime<Utc>>,
+}
+
+fn get_draft(cxn: &Connection, session: &str) -> Result<Option<SavedDraft>, AppError> {
+    const GET: &str = r#"
+        SELECT decrypt_body(draft), saved_at FROM draft WHERE session = $1
+    "#;
+    let draft: Option<(String, Option<i64>)> = cxn
+        .query_row(GET, [session], |r| Ok((r.get(0)?, r.get(1)?)))
+        .optional()
+        .map_err(convert_db_error)?;
+    match draft {
+        Some((body, saved_at)) => Ok(Some(SavedDraft {
+            body,
+            saved_at: saved_at.map(convert_timestamp).transpose()?,
+        })),
+        None => Ok(None),
+    }
+}
+
+/// Throw away a session's draft without saving it as an entry, for the editor's "Discard"
+/// button.
+async fn post_clear_draft(
+    Extension(cxn_arcmux): Extension<ConnectionArcMux>,
+    site: Site,
+    ApiQuery(query): ApiQuery<DraftSession>,
+) -> Result<Json<DraftStatus>, ApiError> {
+    site.check_writable()?;
+    let cxn = lock_db(&cxn_arcmux, &site).await?;
+    clear_draft(&cxn, &query.session)?;
+    if query.adopt {
+        clear_draft(&cxn, "")?;
+    }
+    Ok(Json(DraftStatus { saved_at: None }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_db() -> Connection {
+        connect_and_init_db(":memory:", None, Tokenizer::default()).expect("test database")
+    }
+
+    fn test_app(cxn: Connection) -> axum::Router {
+        newapp(
+            vec![(String::from("test"), cxn)],
+            SiteOptions::default(),
+            std::time::Duration::from_secs(60),
+        )
+    }
+
+    async fn send(app: &axum::Router, req: Request<Body>) -> (StatusCode, String) {
+        let response = app.clone().oneshot(req).await.expect("response");
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body())
+            .await
+            .expect("body");
+        (status, String::from_utf8_lossy(&body).into_owned())
+    }
+
+    async fn get(app: &axum::Router, uri: &str) -> (StatusCode, String) {
+        send(app, Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    async fn post_form(app: &axum::Router, uri: &str, form: &str) -> (StatusCode, String) {
+        let req = Request::post(uri)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_owned()))
+            .unwrap();
+        send(app, req).await
+    }
+
+    /// A path for a database file that's removed when the test is done with it.
+    struct TempDb(std::path::PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "web-diary-test-{}-{}.sqlite3",
+                std::process::id(),
+                name
+            ));
+            let _ = std::fs::remove_file(&path);
+            TempDb(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().expect("UTF-8 temp path")
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only_database_is_served_without_writes() {
+        let db = TempDb::new("read-only");
+        drop(connect_and_init_db(db.path(), None, Tokenizer::default()).unwrap());
+
+        let uri = format!("file:{}?mode=ro", db.path());
+        let cxn = connect_and_init_db(&uri, None, Tokenizer::default()).unwrap();
+        assert!(is_read_only(&cxn).unwrap());
+        let app = test_app(cxn);
+        let (status, _) = get(&app, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = post_form(&app, "/new", "body=hello").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert!(body.contains("read-only"));
+    }
+
+    #[tokio::test]
+    async fn entries_inserted_without_updated_at_can_be_shown() {
+        let cxn = test_db();
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body) VALUES (1700000000, '2023-11-14', 'Hi')",
+            [],
+        )
+        .unwrap();
+        let updated_at: Option<i64> = cxn
+            .query_row("SELECT updated_at FROM entries WHERE rowid = 1", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(updated_at, Some(1700000000));
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body, updated_at) VALUES (1700100000, '2023-11-16', 'Hi', NULL)",
+            [],
+        )
+        .unwrap();
+        cxn.execute("UPDATE entries SET updated_at = NULL WHERE rowid = 2", [])
+            .unwrap();
+
+        let app = test_app(cxn);
+        for uri in ["/", "/entry/1", "/entry/2"] {
+            let (status, _) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn out_of_range_tz_offsets_are_rejected() {
+        let cxn = test_db();
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body) VALUES (1700000000, '2023-11-14', 'Hi')",
+            [],
+        )
+        .unwrap();
+        let app = test_app(cxn);
+        for offset in ["-300", "99999999", "-2147483648"] {
+            let expected = if offset == "-300" {
+                StatusCode::OK
+            } else {
+                StatusCode::BAD_REQUEST
+            };
+            for uri in ["/entry/1", "/api/activity-by-hour"] {
+                let (status, _) = get(&app, &format!("{}?tz_offset={}", uri, offset)).await;
+                assert_eq!(status, expected, "{} {}", uri, offset);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn deleted_ranges_are_reported_as_changes() {
+        let cxn = test_db();
+        for (timestamp, date) in [(1700000000, "2023-11-14"), (1700100000, "2023-11-16")] {
+            cxn.execute(
+                "INSERT INTO entries (timestamp, date, body) VALUES ($1, $2, 'Hi')",
+                rusqlite::params![timestamp, date],
+            )
+            .unwrap();
+        }
+        let app = test_app(cxn);
+        let (status, body) = post_form(
+            &app,
+            "/admin/delete-range",
+            "from=2023-11-14&to=2023-11-15&count=1",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Deleted 1 entries"));
+
+        let (status, body) = get(&app, "/api/changes?since=0").await;
+        assert_eq!(status, StatusCode::OK);
+        let changes: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(changes["entries"].as_array().unwrap().len(), 1);
+        assert_eq!(changes["entries"][0]["id"], 2);
+        assert_eq!(changes["deleted"].as_array().unwrap().len(), 1);
+        assert_eq!(changes["deleted"][0]["id"], 1);
+    }
+
+    #[tokio::test]
+    async fn drafts_are_kept_per_session() {
+        let app = test_app(test_db());
+        for form in ["session=a&body=From+a", "session=b&body=From+b"] {
+            let (status, _) = post_form(&app, "/draft", form).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let (_, body) = get(&app, "/new").await;
+        assert!(!body.contains("id=\"other-drafts\" class=\"banner\" hidden"));
+
+        let (status, _) = post_form(&app, "/draft/clear?session=a", "").await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, body) = get(&app, "/draft?session=b").await;
+        let draft: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(draft["body"], "From b");
+        assert_eq!(draft["other_drafts"], 0);
+
+        let (status, _) = post_form(&app, "/draft", "session=a&body=Again").await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = post_form(&app, "/new", "session=a&body=Again").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let (_, body) = get(&app, "/draft?session=a").await;
+        let draft: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(draft["body"], serde_json::Value::Null);
+        assert_eq!(draft["other_drafts"], 1);
+    }
+
+    #[tokio::test]
+    async fn entries_with_malformed_dates_are_skipped() {
+        let mut cxn = test_db();
+        for (timestamp, date) in [(1700000000, "2023-11-14"), (1700100000, "not a date")] {
+            cxn.execute(
+                "INSERT INTO entries (timestamp, date, body) VALUES ($1, $2, 'Hi')",
+                rusqlite::params![timestamp, date],
+            )
+            .unwrap();
+        }
+        assert_eq!(year_counts(&mut cxn).unwrap(), vec![(2023, 1)]);
+
+        let app = test_app(cxn);
+        let (status, body) = get(&app, "/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("2023"));
+        let (status, _) = get(&app, "/year/2023").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[test]
+    fn bare_urls_are_linked() {
+        let render = |markdown| render_markdown(markdown, MarkdownOptions::default());
+
+        let html = render("See https://example.com/a_b_c.");
+        assert_eq!(html.matches("<a ").count(), 1);
+        assert!(html.contains("href=\"https://example.com/a_b_c\""));
+        assert!(html.contains("</a>.</p>"));
+
+        let html = render("[a link](https://example.com)");
+        assert_eq!(html.matches("<a ").count(), 1);
+        assert!(html.contains(">a link</a>"));
+
+        let html = render("Run `curl https://example.com` first.");
+        assert!(!html.contains("<a "));
+        assert!(html.contains("<code>curl https://example.com</code>"));
+    }
+
+    #[tokio::test]
+    async fn api_years_without_entries_are_not_found() {
+        let app = test_app(test_db());
+        let (status, body) = get(&app, "/api/year/2023").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(error["status"], 404);
+    }
+
+    #[tokio::test]
+    async fn years_outside_the_range_are_rejected() {
+        assert_eq!(check_year(0).unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert_eq!(check_year(999999).unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert!(check_year(2023).is_ok());
+
+        let app = test_app(test_db());
+        for (uri, expected) in [
+            ("/year/0", StatusCode::BAD_REQUEST),
+            ("/year/999999", StatusCode::BAD_REQUEST),
+            ("/year/2023", StatusCode::NOT_FOUND),
+        ] {
+            let (status, _) = get(&app, uri).await;
+            assert_eq!(status, expected, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn api_routes_report_unreadable_requests_as_json() {
+        let app = test_app(test_db());
+        let json_request = |uri: &str| {
+            Request::get(uri)
+                .header("accept", "application/json")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let requests = [
+            json_request("/api/changes"),
+            json_request("/api/changes?since=abc"),
+            json_request("/api/search?page=x"),
+            json_request("/api/year/abc"),
+            json_request("/entry/abc"),
+            json_request("/entry/1?tz_offset=abc"),
+            json_request("/entry/1"),
+            Request::post("/api/entry")
+                .header("content-type", "application/json")
+                .body(Body::from("{\"body\": "))
+                .unwrap(),
+        ];
+        for req in requests {
+            let uri = req.uri().clone();
+            let (status, body) = send(&app, req).await;
+            assert!(status.is_client_error(), "{}: {}", uri, status);
+            let error: serde_json::Value = serde_json::from_str(&body)
+                .unwrap_or_else(|_| panic!("{} didn't answer in JSON: {}", uri, body));
+            assert_eq!(error["status"], status.as_u16(), "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn double_submissions_are_saved_once() {
+        let cxn = test_db();
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body) VALUES (1700000000, '2023-11-14', 'Same')",
+            [],
+        )
+        .unwrap();
+        let app = test_app(cxn);
+        let location = |form| {
+            let app = &app;
+            async move {
+                let req = Request::post("/new")
+                    .header("content-type", "application/x-www-form-urlencoded")
+                    .body(Body::from(form))
+                    .unwrap();
+                let response = app.clone().oneshot(req).await.unwrap();
+                response.headers()["location"].to_str().unwrap().to_owned()
+            }
+        };
+        // The old entry with the same text is outside the window.
+        assert_eq!(location("body=Same").await, "/entry/2");
+        assert_eq!(location("body=Same").await, "/entry/2");
+        assert_eq!(location("body=Different").await, "/entry/3");
+    }
+
+    #[test]
+    fn only_checkbox_inputs_survive_sanitizing() {
+        let html = render_markdown(
+            "- [x] Done\n\n<input type=\"text\" value=\"x\" autofocus>",
+            MarkdownOptions::default(),
+        );
+        assert_eq!(html.matches("<input").count(), 2);
+        assert_eq!(html.matches("type=\"checkbox\"").count(), 2);
+        assert!(!html.contains("text"));
+        assert!(!html.contains("autofocus"));
+    }
+
+    #[tokio::test]
+    async fn toggling_a_missing_task_is_a_json_error() {
+        let cxn = test_db();
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body, updated_at) VALUES (1700000000, '2023-11-14', '- [ ] One', 1700000000)",
+            [],
+        )
+        .unwrap();
+        let app = test_app(cxn);
+        for (form, expected) in [
+            ("task=1&updated_at=1700000000", StatusCode::NOT_FOUND),
+            ("task=0&updated_at=1", StatusCode::CONFLICT),
+            ("task=x", StatusCode::BAD_REQUEST),
+        ] {
+            let (status, body) = post_form(&app, "/entry/1/toggle-task", form).await;
+            assert_eq!(status, expected, "{}", form);
+            let error: serde_json::Value = serde_json::from_str(&body).unwrap();
+            assert_eq!(error["status"], expected.as_u16());
+        }
+        let (status, body) =
+            post_form(&app, "/entry/1/toggle-task", "task=0&updated_at=1700000000").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("\"checked\":true"));
+    }
+
+    #[tokio::test]
+    async fn unreadable_page_queries_get_the_error_page() {
+        let app = test_app(test_db());
+        for uri in ["/?order=bogus", "/search?page=x", "/day"] {
+            let (status, body) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+            assert!(body.contains("<html"), "{}: {}", uri, body);
+        }
+        let (status, _) = get(&app, "/?order=oldest").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn search_works_without_a_result_limit() {
+        let cxn = test_db();
+        create_entry(&cxn, "Hello there", None).unwrap();
+        let count = count_search_results(&cxn, "hello", u32::MAX).unwrap();
+        assert_eq!((count.total, count.capped), (1, false));
+
+        let options = SiteOptions {
+            max_search_results: u32::MAX,
+            ..SiteOptions::default()
+        };
+        let app = newapp(
+            vec![(String::from("test"), cxn)],
+            options,
+            std::time::Duration::from_secs(60),
+        );
+        for uri in ["/search?q=hello", "/search?q=hello&page=214748364"] {
+            let (status, _) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+        }
+    }
+
+    #[tokio::test]
+    async fn undo_only_reverses_the_edit_the_page_offered() {
+        let cxn = test_db();
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body, updated_at) VALUES (1700000000, '2023-11-14', '- [ ] One', 1700000000)",
+            [],
+        )
+        .unwrap();
+        let app = test_app(cxn);
+        let (status, body) =
+            post_form(&app, "/entry/1/toggle-task", "task=0&updated_at=1700000000").await;
+        assert_eq!(status, StatusCode::OK);
+        let toggled: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let form = format!("task=0&updated_at={}", toggled["updated_at"]);
+        let (status, _) = post_form(&app, "/entry/1/toggle-task", &form).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, page) = get(&app, "/entry/1").await;
+        assert!(page.contains("name=\"action\" value=\"2\""));
+        let (status, _) = post_form(&app, "/undo", "action=1").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = post_form(&app, "/undo", "action=2").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        // A second click on the same button.
+        let (status, _) = post_form(&app, "/undo", "action=2").await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (_, body) = get(&app, "/entry/1/raw").await;
+        assert!(body.contains("[x] One"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn deleting_a_range_needs_the_confirmed_count_and_the_same_site() {
+        let cxn = test_db();
+        for (timestamp, date) in [(1700000000, "2023-11-14"), (1700100000, "2023-11-15")] {
+            cxn.execute(
+                "INSERT INTO entries (timestamp, date, body) VALUES ($1, $2, 'Hi')",
+                rusqlite::params![timestamp, date],
+            )
+            .unwrap();
+        }
+        let app = test_app(cxn);
+        let (_, page) = get(&app, "/admin/delete-range?from=2023-11-14&to=2023-11-15").await;
+        assert!(page.contains("name=\"count\" value=\"2\""));
+
+        let form = "from=2023-11-14&to=2023-11-15&count=1";
+        let (status, _) = post_form(&app, "/admin/delete-range", form).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let form = "from=2023-11-14&to=2023-11-15&count=2";
+        let cross_site = [
+            ("sec-fetch-site", "cross-site"),
+            ("origin", "https://evil.example"),
+        ];
+        for (name, value) in cross_site {
+            let req = Request::post("/admin/delete-range")
+                .header("content-type", "application/x-www-form-urlencoded")
+                .header("host", "diary.example")
+                .header(name, value)
+                .body(Body::from(form))
+                .unwrap();
+            let (status, _) = send(&app, req).await;
+            assert_eq!(status, StatusCode::FORBIDDEN, "{}", name);
+        }
+        let req = Request::post("/admin/delete-range")
+            .header("content-type", "application/x-www-form-urlencoded")
+            .header("host", "diary.example")
+            .header("origin", "https://diary.example")
+            .header("sec-fetch-site", "same-origin")
+            .body(Body::from(form))
+            .unwrap();
+        let (status, body) = send(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("Deleted 2 entries"));
+    }
+
+    #[test]
+    fn slugs_are_capped_at_forty_characters() {
+        assert_eq!(
+            slug("\nDear Diary, today was GOOD.\n"),
+            "dear-diary-today-was-good"
+        );
+        assert_eq!(
+            slug("one two three four five six seven eight nine"),
+            "one-two-three-four-five-six-seven-eight"
+        );
+        assert_eq!(slug(&"a".repeat(300)), "a".repeat(40));
+        let cjk = "今日は".repeat(100);
+        let capped = slug(&cjk);
+        assert_eq!(capped.chars().count(), 40);
+        assert!(cjk.starts_with(&capped));
+    }
+
+    #[tokio::test]
+    async fn year_pages_change_when_other_years_are_written() {
+        let cxn = test_db();
+        cxn.execute(
+            "INSERT INTO entries (timestamp, date, body, updated_at) VALUES (1700000000, '2023-11-14', 'Hi', 1700000000)",
+            [],
+        )
+        .unwrap();
+        let app = test_app(cxn);
+        let response = app
+            .clone()
+            .oneshot(Request::get("/year/2023").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let etag = response.headers()["etag"].to_str().unwrap().to_owned();
+        let last_modified = response.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let revalidate = |name: &'static str, value: &str| {
+            Request::get("/year/2023")
+                .header(name, value)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let (status, _) = send(&app, revalidate("if-none-match", &etag)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        let (status, _) = post_form(&app, "/new", "body=Another+year").await;
+        assert_eq!(status, StatusCode::SEE_OTHER);
+        let (status, body) = send(&app, revalidate("if-none-match", &etag)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("2 entries"), "{}", body);
+        let (status, _) = send(&app, revalidate("if-modified-since", &last_modified)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn year_pages_change_when_entries_are_deleted() {
+        let cxn = test_db();
+        for (timestamp, date) in [(1700000000, "2023-11-14"), (1700100000, "2023-11-16")] {
+            cxn.execute(
+                "INSERT INTO entries (timestamp, date, body) VALUES ($1, $2, 'Hi')",
+                rusqlite::params![timestamp, date],
+            )
+            .unwrap();
+        }
+        // Rows from before `updated_at` was always filled in.
+        cxn.execute("UPDATE entries SET updated_at = NULL", [])
+            .unwrap();
+        let app = test_app(cxn);
+        let conditional_get = |since: &str| {
+            Request::get("/year/2023")
+                .header("if-modified-since", since)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let response = app
+            .clone()
+            .oneshot(Request::get("/year/2023").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let last_modified = response.headers()["last-modified"]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let (status, _) = send(&app, conditional_get(&last_modified)).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+        let (status, _) = post_form(
+            &app,
+            "/admin/delete-range",
+            "from=2023-11-14&to=2023-11-14&count=1",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, conditional_get(&last_modified)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = post_form(
+            &app,
+            "/admin/delete-range",
+            "from=2023-11-16&to=2023-11-16&count=1",
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get(&app, "/year/2023").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Set when the test binary is rerun by `templates_render_without_files_on_disk`.
+    const IN_EMPTY_DIR: &str = "WEB_DIARY_TEST_IN_EMPTY_DIR";
+
+    /// Reruns itself in a subprocess started in an empty directory, since changing this
+    /// process's working directory would affect the tests running alongside it.
+    #[tokio::test]
+    async fn templates_render_without_files_on_disk() {
+        const NAME: &str = "tests::templates_render_without_files_on_disk";
+        if std::env::var_os(IN_EMPTY_DIR).is_none() {
+            let dir = std::env::temp_dir().join(format!("web-diary-test-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", NAME, "--test-threads=1"])
+                .env(IN_EMPTY_DIR, "1")
+                .current_dir(&dir)
+                .output()
+                .unwrap();
+            std::fs::remove_dir(&dir).unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(
+                output.status.success(),
+                "{}{}",
+                stdout,
+                String::from_utf8_lossy(&output.stderr)
+            );
+            assert!(stdout.contains("1 passed"), "{}", stdout);
+            return;
+        }
+        assert!(!std::path::Path::new("templates").exists());
+
+        let app = test_app(test_db());
+        for uri in ["/", "/new", "/search", "/admin/log.html", "/admin/errors"] {
+            let (status, body) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::OK, "{}", uri);
+            assert!(body.contains("</html>"), "{}", uri);
+        }
+        let (status, body) = send(&app, Request::delete("/").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+        assert!(body.contains("</html>"));
+    }
 }